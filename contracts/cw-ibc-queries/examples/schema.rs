@@ -3,7 +3,10 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use cw_ibc_queries::msg::{ExecuteMsg, InstantiateMsg};
+use cw_ibc_queries::msg::{
+    AdminResponse, ChannelResponse, ExecuteMsg, InstantiateMsg, QueryMsg,
+    StargateAllowlistResponse, SubscriptionResponse, SubscriptionsResponse,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -13,4 +16,10 @@ fn main() {
 
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
+    export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(AdminResponse), &out_dir);
+    export_schema(&schema_for!(StargateAllowlistResponse), &out_dir);
+    export_schema(&schema_for!(ChannelResponse), &out_dir);
+    export_schema(&schema_for!(SubscriptionResponse), &out_dir);
+    export_schema(&schema_for!(SubscriptionsResponse), &out_dir);
 }
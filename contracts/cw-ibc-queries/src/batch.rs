@@ -0,0 +1,326 @@
+use cosmwasm_std::{
+    CosmosMsg, Deps, DepsMut, Env, IbcMsg, MessageInfo, Order, QueryRequest, Response, StdResult,
+    Storage,
+};
+use cw_ibc_query::{IbcQueryResult, ProtocolVersion, ReceiveIbcQueryResponseMsg};
+
+use crate::contract::{assert_admin, send_query};
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::ibc::channel_version;
+use crate::msg::BatchQueueResponse;
+use crate::state::{next_query_id, BatchPart, QueuedQuery, BATCHES, BATCHING, CHANNELS, QUEUE};
+
+/// Queues a request on a channel with batching on. The queue is flushed first if the request
+/// wouldn't fit in the packet anymore, and after it once it holds `flush_at` queries.
+pub fn enqueue_query(
+    mut deps: DepsMut,
+    env: &Env,
+    channel_id: String,
+    msgs: Vec<QueryRequest<HostQuery>>,
+    callback: String,
+    flush_at: u32,
+) -> Result<Response, ContractError> {
+    // the queries must be fine for the channel, or the flush fails for everyone
+    let negotiated = channel_version(deps.as_ref(), &channel_id)?;
+    negotiated.supports_all(&msgs)?;
+
+    let mut res = Response::new();
+    let mut queued = queued_queries(deps.storage, &channel_id)?;
+    if queued > 0 && queued + msgs.len() as u32 > negotiated.max_batch_size {
+        res = res.add_messages(flush(deps.branch(), env, &channel_id)?);
+        queued = 0;
+    }
+
+    let query_id = next_query_id(deps.storage)?;
+    queued += msgs.len() as u32;
+    let request = QueuedQuery { msgs, callback };
+    QUEUE.save(deps.storage, (&channel_id, query_id), &request)?;
+    if queued >= flush_at {
+        res = res.add_messages(flush(deps, env, &channel_id)?);
+    }
+
+    Ok(res
+        .add_attribute("action", "handle_check_remote_balance")
+        .add_attribute("query_id", query_id.to_string())
+        .add_attribute("queued", "true"))
+}
+
+pub fn execute_flush(
+    deps: DepsMut,
+    env: Env,
+    channel_id: String,
+) -> Result<Response, ContractError> {
+    let msg = flush(deps, &env, &channel_id)?.ok_or(ContractError::EmptyQueue(channel_id))?;
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "flush"))
+}
+
+/// Admin only, queues requests on the channel until `flush_at` queries are waiting. `None`
+/// turns batching off, what's queued can still be flushed.
+pub fn execute_update_batching(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel_id: String,
+    flush_at: Option<u32>,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    match flush_at {
+        Some(flush_at) => {
+            // v1 callbacks get the raw ack, which can't be split
+            match CHANNELS.may_load(deps.storage, &channel_id)? {
+                Some(channel) if channel.negotiated.version != ProtocolVersion::V1 => {}
+                _ => return Err(ContractError::BatchingUnsupported(channel_id)),
+            }
+            BATCHING.save(deps.storage, &channel_id, &flush_at)?;
+        }
+        None => BATCHING.remove(deps.storage, &channel_id),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_batching")
+        .add_attribute("channel_id", channel_id))
+}
+
+/// Packs everything queued on the channel into one packet
+fn flush(mut deps: DepsMut, env: &Env, channel_id: &str) -> Result<Option<IbcMsg>, ContractError> {
+    let queued: Vec<(u64, QueuedQuery)> = QUEUE
+        .prefix(channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    if queued.is_empty() {
+        return Ok(None);
+    }
+
+    let mut msgs = vec![];
+    let mut parts = vec![];
+    for (query_id, request) in queued {
+        QUEUE.remove(deps.storage, (channel_id, query_id));
+        parts.push(BatchPart {
+            query_id,
+            callback: request.callback,
+            len: request.msgs.len() as u32,
+        });
+        msgs.extend(request.msgs);
+    }
+    // the ack comes back to us and is split by `parts`
+    let contract = env.contract.address.to_string();
+    let (packet_id, msg) = send_query(
+        deps.branch(),
+        env,
+        contract.clone(),
+        channel_id.to_string(),
+        msgs,
+        contract,
+    )?;
+    BATCHES.save(deps.storage, packet_id, &parts)?;
+    Ok(Some(msg))
+}
+
+/// The callers of a flushed packet, if it was one
+pub fn take_batch(storage: &mut dyn Storage, packet_id: u64) -> StdResult<Option<Vec<BatchPart>>> {
+    let parts = BATCHES.may_load(storage, packet_id)?;
+    BATCHES.remove(storage, packet_id);
+    Ok(parts)
+}
+
+/// One callback per caller, with their share of the results
+pub fn split_batch(
+    parts: Vec<BatchPart>,
+    channel_id: &str,
+    result: &IbcQueryResult,
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut offset = 0;
+    parts
+        .into_iter()
+        .map(|part| {
+            let end = offset + part.len as usize;
+            let result = match result {
+                IbcQueryResult::Ok(results) => match results.get(offset..end) {
+                    Some(results) => IbcQueryResult::Ok(results.to_vec()),
+                    None => IbcQueryResult::Error(format!(
+                        "Host answered {} of the batch's queries",
+                        results.len()
+                    )),
+                },
+                other => other.clone(),
+            };
+            offset = end;
+            ReceiveIbcQueryResponseMsg {
+                channel_id: channel_id.to_string(),
+                query_id: part.query_id,
+                result,
+            }
+            .into_cosmos_msg(part.callback)
+        })
+        .collect()
+}
+
+fn queued_queries(storage: &dyn Storage, channel_id: &str) -> StdResult<u32> {
+    QUEUE
+        .prefix(channel_id)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, request)| request.msgs.len() as u32))
+        .sum()
+}
+
+pub fn query_batch_queue(deps: Deps, channel_id: String) -> StdResult<BatchQueueResponse> {
+    Ok(BatchQueueResponse {
+        flush_at: BATCHING.may_load(deps.storage, &channel_id)?,
+        queued: queued_queries(deps.storage, &channel_id)?,
+        channel_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_packet_ack, mock_info};
+    use cosmwasm_std::{from_binary, Binary, ContractResult, Empty, IbcAcknowledgement, WasmMsg};
+    use cw_ibc_query::{ReceiverExecuteMsg, WireFormat};
+
+    use crate::contract::{execute, query};
+    use crate::ibc::ibc_packet_ack;
+    use crate::msg::{ExecuteMsg, QueryMsg};
+    use crate::testing::{balance_query, json_v2, packets, setup, setup_channel};
+
+    use super::*;
+
+    const ADMIN: &str = "admin";
+    const CHANNEL: &str = "channel-9";
+
+    fn result(data: &str) -> ContractResult<Binary> {
+        ContractResult::Ok(Binary::from(data.as_bytes()))
+    }
+
+    fn batching(flush_at: Option<u32>) -> ExecuteMsg {
+        ExecuteMsg::UpdateBatching {
+            channel_id: CHANNEL.to_string(),
+            flush_at,
+        }
+    }
+
+    /// A channel that sends once 3 queries are queued
+    fn setup_batching(mut deps: DepsMut) {
+        setup(deps.branch(), ADMIN);
+        setup_channel(deps.branch(), CHANNEL, json_v2(10));
+        execute(deps, mock_env(), mock_info(ADMIN, &[]), batching(Some(3))).unwrap();
+    }
+
+    #[test]
+    fn only_admin_updates_batching() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), ADMIN);
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+
+        let info = mock_info("requester", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, batching(Some(3)));
+        assert_eq!(err.unwrap_err(), ContractError::Unauthorized);
+        let info = mock_info(ADMIN, &[]);
+        execute(deps.as_mut(), mock_env(), info, batching(Some(3))).unwrap();
+    }
+
+    #[test]
+    fn flush_needs_queued_queries() {
+        let mut deps = mock_dependencies();
+        setup_batching(deps.as_mut());
+
+        let flush = ExecuteMsg::Flush {
+            channel_id: CHANNEL.to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("requester", &[]),
+            flush,
+        );
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::EmptyQueue(CHANNEL.to_string())
+        );
+    }
+
+    #[test]
+    fn queries_wait_until_flush_at() {
+        let mut deps = mock_dependencies();
+        setup_batching(deps.as_mut());
+
+        let msg = balance_query(CHANNEL, &["treasury", "pool"], "alice", None);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("requester", &[]), msg).unwrap();
+        assert!(packets(&res).is_empty());
+        let msg = QueryMsg::BatchQueue {
+            channel_id: CHANNEL.to_string(),
+        };
+        let queue: BatchQueueResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(queue.flush_at, Some(3));
+        assert_eq!(queue.queued, 2);
+    }
+
+    #[test]
+    fn queued_queries_share_a_packet() {
+        let mut deps = mock_dependencies();
+        setup_batching(deps.as_mut());
+
+        let requester = mock_info("requester", &[]);
+        let msg = balance_query(CHANNEL, &["treasury", "pool"], "alice", None);
+        execute(deps.as_mut(), mock_env(), requester.clone(), msg).unwrap();
+        let msg = balance_query(CHANNEL, &["treasury"], "bob", None);
+        let res = execute(deps.as_mut(), mock_env(), requester, msg).unwrap();
+        let data = packets(&res).remove(0).data;
+
+        // the answer is split between the callers
+        let results = vec![result("1"), result("2"), result("3")];
+        let ack = WireFormat::from(ProtocolVersion::V2)
+            .encode_ack(3, results)
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(CHANNEL, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = data;
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        let callbacks: Vec<_> = res
+            .messages
+            .iter()
+            .map(|msg| match &msg.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => (contract_addr.clone(), from_binary(msg).unwrap()),
+                _ => panic!("expected a callback"),
+            })
+            .collect();
+        assert_eq!(
+            callbacks,
+            vec![
+                (
+                    "alice".to_string(),
+                    ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                        channel_id: CHANNEL.to_string(),
+                        query_id: 1,
+                        result: IbcQueryResult::Ok(vec![result("1"), result("2")]),
+                    })
+                ),
+                (
+                    "bob".to_string(),
+                    ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                        channel_id: CHANNEL.to_string(),
+                        query_id: 2,
+                        result: IbcQueryResult::Ok(vec![result("3")]),
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn requests_too_large_for_a_packet_are_sharded() {
+        let mut deps = mock_dependencies();
+        setup_batching(deps.as_mut());
+
+        let addresses = ["treasury"; 11];
+        let msg = balance_query(CHANNEL, &addresses, "alice", None);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("requester", &[]), msg).unwrap();
+        assert_eq!(packets(&res).len(), 2);
+        let queue = query_batch_queue(deps.as_ref(), CHANNEL.to_string()).unwrap();
+        assert_eq!(queue.queued, 0);
+    }
+}
@@ -0,0 +1,215 @@
+use cosmwasm_std::{to_vec, Binary, ContractResult, Env, Order, QueryRequest, StdResult, Storage};
+use cw_ibc_query::IbcQueryResult;
+use sha2::{Digest, Sha256};
+
+use crate::custom_query::HostQuery;
+use crate::state::{CachedResult, CACHE_TTL, RESULT_CACHE};
+
+/// Identifies a query in the cache
+pub fn query_hash(query: &QueryRequest<HostQuery>) -> StdResult<Vec<u8>> {
+    Ok(Sha256::digest(to_vec(query)?).to_vec())
+}
+
+/// Identifies a whole batch of queries
+pub fn batch_hash(msgs: &[QueryRequest<HostQuery>]) -> StdResult<Vec<u8>> {
+    Ok(Sha256::digest(to_vec(msgs)?).to_vec())
+}
+
+/// Remembers the successful results of an answered packet, if caching is on
+pub fn cache_results(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    msgs: &[QueryRequest<HostQuery>],
+    result: &IbcQueryResult,
+) -> StdResult<()> {
+    let results = match result {
+        IbcQueryResult::Ok(results) if results.len() == msgs.len() => results,
+        _ => return Ok(()),
+    };
+    if CACHE_TTL.may_load(storage)?.unwrap_or_default() == 0 {
+        return Ok(());
+    }
+    for (query, result) in msgs.iter().zip(results) {
+        if let ContractResult::Ok(data) = result {
+            let cached = CachedResult {
+                data: data.clone(),
+                cached_at: env.block.time,
+            };
+            RESULT_CACHE.save(storage, (channel_id, &query_hash(query)?), &cached)?;
+        }
+    }
+    Ok(())
+}
+
+/// Results for all of `msgs` that are at most `max_age` seconds old, and younger than the
+/// cache TTL. None if any is missing. Results older than the TTL are evicted on the way.
+pub fn cached_results(
+    storage: &mut dyn Storage,
+    env: &Env,
+    channel_id: &str,
+    msgs: &[QueryRequest<HostQuery>],
+    max_age: u64,
+) -> StdResult<Option<Vec<ContractResult<Binary>>>> {
+    let ttl = CACHE_TTL.may_load(storage)?.unwrap_or_default();
+    if msgs.is_empty() || ttl == 0 {
+        return Ok(None);
+    }
+    let max_age = max_age.min(ttl);
+    let mut results = Some(Vec::with_capacity(msgs.len()));
+    for query in msgs {
+        let hash = query_hash(query)?;
+        let key = (channel_id, hash.as_slice());
+        match RESULT_CACHE.may_load(storage, key)? {
+            Some(cached) if env.block.time > cached.cached_at.plus_seconds(ttl) => {
+                RESULT_CACHE.remove(storage, key);
+                results = None;
+            }
+            Some(cached) if env.block.time <= cached.cached_at.plus_seconds(max_age) => {
+                if let Some(results) = &mut results {
+                    results.push(ContractResult::Ok(cached.data));
+                }
+            }
+            _ => results = None,
+        }
+    }
+    Ok(results)
+}
+
+/// Forgets every cached result, once caching is turned off
+pub fn clear_cache(storage: &mut dyn Storage) -> StdResult<()> {
+    let keys = RESULT_CACHE
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (channel_id, hash) in keys {
+        RESULT_CACHE.remove(storage, (&channel_id, &hash));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_packet_ack, mock_info};
+    use cosmwasm_std::{from_binary, CosmosMsg, DepsMut, Empty, IbcAcknowledgement, WasmMsg};
+    use cw_ibc_query::{
+        ProtocolVersion, ReceiveIbcQueryResponseMsg, ReceiverExecuteMsg, WireFormat,
+    };
+
+    use crate::contract::execute;
+    use crate::ibc::ibc_packet_ack;
+    use crate::msg::ExecuteMsg;
+    use crate::testing::{balance_query, json_v2, packets, setup, setup_channel};
+
+    use super::*;
+
+    const ADMIN: &str = "admin";
+    const CHANNEL: &str = "channel-5";
+
+    fn results() -> Vec<ContractResult<Binary>> {
+        vec![ContractResult::Ok(Binary::from(b"{\"amount\":[]}"))]
+    }
+
+    /// Caches for a minute and answers query 1, so its results are cached at `mock_env()`
+    fn setup_cached(mut deps: DepsMut) {
+        setup(deps.branch(), ADMIN);
+        setup_channel(deps.branch(), CHANNEL, json_v2(10));
+        let ttl = ExecuteMsg::UpdateCacheTtl { ttl: 60 };
+        execute(deps.branch(), mock_env(), mock_info(ADMIN, &[]), ttl).unwrap();
+
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.branch(),
+            mock_env(),
+            info,
+            balance_query(CHANNEL, &["treasury"], "callback", Some(30)),
+        );
+        let data = packets(&res.unwrap()).remove(0).data;
+        let ack = WireFormat::from(ProtocolVersion::V2)
+            .encode_ack(1, results())
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(CHANNEL, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = data;
+        ibc_packet_ack(deps, mock_env(), msg).unwrap();
+    }
+
+    #[test]
+    fn fresh_results_answer_in_the_same_transaction() {
+        let mut deps = mock_dependencies();
+        setup_cached(deps.as_mut());
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30);
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.as_mut(),
+            env,
+            info,
+            balance_query(CHANNEL, &["treasury"], "callback", Some(30)),
+        );
+        let callback: ReceiverExecuteMsg = match &res.unwrap().messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).unwrap(),
+            _ => panic!("expected a callback"),
+        };
+        assert_eq!(
+            callback,
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: CHANNEL.to_string(),
+                query_id: 2,
+                result: IbcQueryResult::Ok(results()),
+            })
+        );
+    }
+
+    #[test]
+    fn older_results_go_over_the_wire() {
+        let mut deps = mock_dependencies();
+        setup_cached(deps.as_mut());
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(30);
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.as_mut(),
+            env,
+            info,
+            balance_query(CHANNEL, &["treasury"], "callback", Some(29)),
+        );
+        assert_eq!(packets(&res.unwrap()).len(), 1);
+    }
+
+    fn cached(storage: &dyn Storage) -> usize {
+        RESULT_CACHE
+            .keys(storage, None, None, Order::Ascending)
+            .count()
+    }
+
+    #[test]
+    fn expired_results_are_evicted() {
+        let mut deps = mock_dependencies();
+        setup_cached(deps.as_mut());
+        assert_eq!(cached(&deps.storage), 1);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(61);
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.as_mut(),
+            env,
+            info,
+            balance_query(CHANNEL, &["treasury"], "callback", Some(30)),
+        );
+        assert_eq!(packets(&res.unwrap()).len(), 1);
+        assert_eq!(cached(&deps.storage), 0);
+    }
+
+    #[test]
+    fn turning_caching_off_clears_it() {
+        let mut deps = mock_dependencies();
+        setup_cached(deps.as_mut());
+
+        let ttl = ExecuteMsg::UpdateCacheTtl { ttl: 0 };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), ttl).unwrap();
+        assert_eq!(cached(&deps.storage), 0);
+    }
+}
@@ -0,0 +1,137 @@
+use cosmwasm_std::{Order, QueryRequest, StdResult, Storage};
+
+use crate::cache::batch_hash;
+use crate::custom_query::HostQuery;
+use crate::state::{IN_FLIGHT, WAITING};
+
+/// The packet already carrying the queries with `hash` to `channel_id`, if any
+pub fn in_flight(storage: &dyn Storage, channel_id: &str, hash: &[u8]) -> StdResult<Option<u64>> {
+    IN_FLIGHT.may_load(storage, (channel_id, hash))
+}
+
+/// Lets later identical requests wait for this packet
+pub fn track(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+    hash: &[u8],
+    packet_id: u64,
+) -> StdResult<()> {
+    IN_FLIGHT.save(storage, (channel_id, hash), &packet_id)
+}
+
+/// Stops tracking an answered packet, returning the query ids and callbacks of the callers
+/// that waited for it
+pub fn release(
+    storage: &mut dyn Storage,
+    channel_id: &str,
+    msgs: &[QueryRequest<HostQuery>],
+    packet_id: u64,
+) -> StdResult<Vec<(u64, String)>> {
+    let hash = batch_hash(msgs)?;
+    if IN_FLIGHT.may_load(storage, (channel_id, &hash))? != Some(packet_id) {
+        return Ok(vec![]);
+    }
+    IN_FLIGHT.remove(storage, (channel_id, &hash));
+    let waiting: Vec<(u64, String)> = WAITING
+        .prefix(packet_id)
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for (query_id, _) in &waiting {
+        WAITING.remove(storage, (packet_id, *query_id));
+    }
+    Ok(waiting)
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_packet_timeout, mock_info};
+    use cosmwasm_std::{from_binary, Binary, CosmosMsg, DepsMut, Empty, IbcBasicResponse, WasmMsg};
+    use cw_ibc_query::{AppVersion, IbcQueryResult, ReceiverExecuteMsg};
+
+    use crate::contract::execute;
+    use crate::ibc::ibc_packet_timeout;
+    use crate::testing::{balance_query, packets, setup, setup_channel};
+
+    const CHANNEL: &str = "channel-8";
+
+    /// Callback address and query id of every timeout callback
+    fn callbacks(res: &IbcBasicResponse) -> Vec<(String, u64)> {
+        res.messages
+            .iter()
+            .map(|msg| match &msg.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => match from_binary(msg).unwrap() {
+                    ReceiverExecuteMsg::ReceiveIbcQueryResponse(msg) => {
+                        assert_eq!(msg.result, IbcQueryResult::Timeout {});
+                        (contract_addr.clone(), msg.query_id)
+                    }
+                    other => panic!("unexpected callback {:?}", other),
+                },
+                _ => panic!("expected a callback"),
+            })
+            .collect()
+    }
+
+    /// Sends alice's query, then bob's and carol's identical ones. Returns the packet sent.
+    fn query_three_times(mut deps: DepsMut) -> Binary {
+        setup(deps.branch(), "admin");
+        setup_channel(deps.branch(), CHANNEL, AppVersion::new(10, None));
+
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.branch(),
+            mock_env(),
+            info.clone(),
+            balance_query(CHANNEL, &["treasury"], "alice", None),
+        );
+        let mut sent = packets(&res.unwrap());
+        assert_eq!(sent.len(), 1);
+        for callback in ["bob", "carol"] {
+            let res = execute(
+                deps.branch(),
+                mock_env(),
+                info.clone(),
+                balance_query(CHANNEL, &["treasury"], callback, None),
+            );
+            assert!(packets(&res.unwrap()).is_empty());
+        }
+        sent.remove(0).data
+    }
+
+    #[test]
+    fn identical_queries_share_a_packet() {
+        let mut deps = mock_dependencies();
+        let data = query_three_times(deps.as_mut());
+
+        let mut msg = mock_ibc_packet_timeout(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = data;
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            callbacks(&res),
+            vec![
+                ("alice".to_string(), 1),
+                ("bob".to_string(), 2),
+                ("carol".to_string(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn answered_queries_go_out_again() {
+        let mut deps = mock_dependencies();
+        let data = query_three_times(deps.as_mut());
+        let mut msg = mock_ibc_packet_timeout(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = data;
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+
+        let info = mock_info("requester", &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            balance_query(CHANNEL, &["treasury"], "alice", None),
+        );
+        assert_eq!(packets(&res.unwrap()).len(), 1);
+    }
+}
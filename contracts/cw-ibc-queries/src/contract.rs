@@ -1,22 +1,59 @@
 use cosmwasm_std::{
-    entry_point, to_binary, DepsMut, Empty, Env, IbcMsg, MessageInfo, QueryRequest, Response,
-    StdResult,
+    entry_point, to_binary, Binary, Deps, DepsMut, Empty, Env, IbcMsg, MessageInfo, Order,
+    QueryRequest, Response, StdResult,
 };
+use cw_storage_plus::Bound;
 
-use cw_ibc_query::PacketMsg;
+use cw_ibc_query::{
+    IbcQueryResult, PacketMsg, PacketMsgV2, ProtocolVersion, ReceiveIbcQueryResponseMsg,
+};
 
+use crate::batch::{enqueue_query, execute_flush, execute_update_batching, query_batch_queue};
+use crate::cache::{batch_hash, cached_results, clear_cache};
+use crate::coalesce::{in_flight, track};
+use crate::custom_query::HostQuery;
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg};
-use crate::state::PACKET_LIFETIME;
+use crate::group::{execute_deliver_query_group, execute_ibc_query_many};
+use crate::ibc::channel_version;
+use crate::msg::{
+    AdminResponse, ChannelResponse, ExecuteMsg, InstantiateMsg, QueryMsg, StargateAllowlistResponse,
+};
+use crate::pagination::execute_ibc_query_paginated;
+use crate::queryability::{execute_set_queryability, query_queryability};
+use crate::responder::{execute_register_responder, execute_remove_responder, query_responders};
+use crate::shard::{plan_shards, send_shards};
+use crate::state::{
+    next_query_id, QueryGroup, ADMIN, BATCHING, CACHE_TTL, CHANNELS, MAX_BATCH_SIZE, PACKETS_SENT,
+    PACKET_LIFETIME, PENDING_QUERIES, STARGATE_ALLOWLIST, WAITING,
+};
+use crate::subscription::{
+    execute_cancel, execute_fund_subscription, execute_set_paused, execute_subscribe,
+    execute_trigger, query_subscription, query_subscriptions,
+};
+use crate::view::{execute_remove_view, execute_set_view, query_views};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_MAX_BATCH_SIZE: u32 = 50;
 
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
     PACKET_LIFETIME.save(deps.storage, &msg.packet_lifetime)?;
+    MAX_BATCH_SIZE.save(
+        deps.storage,
+        &msg.max_batch_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE),
+    )?;
+    let admin = match msg.admin {
+        Some(admin) => deps.api.addr_validate(&admin)?,
+        None => info.sender,
+    };
+    ADMIN.save(deps.storage, &admin)?;
+    CACHE_TTL.save(deps.storage, &msg.cache_ttl.unwrap_or_default())?;
     Ok(Response::new())
 }
 
@@ -27,56 +64,461 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
-    cw_utils::nonpayable(&info)?;
+    // only deposits for subscriptions are accepted
+    if !matches!(
+        msg,
+        ExecuteMsg::Subscribe(_) | ExecuteMsg::FundSubscription { .. }
+    ) {
+        cw_utils::nonpayable(&info)?;
+    }
     match msg {
         ExecuteMsg::IbcQuery {
             channel_id,
             msgs,
             callback,
-        } => execute_ibc_query(deps, env, info, channel_id, msgs, callback),
+            max_age,
+            projections,
+        } if !projections.is_empty() => {
+            if max_age.is_some() {
+                return Err(ContractError::CachedProjections);
+            }
+            let msg = PacketMsg::IbcQuery {
+                msgs,
+                callback,
+                projections,
+                chained: false,
+                aggregation: None,
+            };
+            execute_direct_query(deps, env, info, channel_id, msg)
+        }
+        ExecuteMsg::IbcQuery {
+            channel_id,
+            msgs,
+            callback,
+            max_age,
+            ..
+        } => execute_ibc_query(deps, env, info, channel_id, msgs, callback, max_age),
+        ExecuteMsg::IbcQueryChain {
+            channel_id,
+            msgs,
+            callback,
+            projections,
+        } => {
+            let msg = PacketMsg::IbcQuery {
+                msgs,
+                callback,
+                projections,
+                chained: true,
+                aggregation: None,
+            };
+            execute_direct_query(deps, env, info, channel_id, msg)
+        }
+        ExecuteMsg::IbcQueryAggregate {
+            channel_id,
+            msgs,
+            callback,
+            aggregation,
+            projections,
+            chained,
+        } => {
+            let msg = PacketMsg::IbcQuery {
+                msgs,
+                callback,
+                projections,
+                chained,
+                aggregation: Some(aggregation),
+            };
+            execute_direct_query(deps, env, info, channel_id, msg)
+        }
+        ExecuteMsg::IbcQueryView {
+            channel_id,
+            name,
+            params,
+            callback,
+        } => {
+            let msg = PacketMsg::View {
+                name,
+                params,
+                callback,
+            };
+            execute_direct_query(deps, env, info, channel_id, msg)
+        }
+        ExecuteMsg::IbcQueryResponder {
+            channel_id,
+            name,
+            request,
+            callback,
+        } => {
+            let msg = PacketMsg::Responder {
+                name,
+                request,
+                callback,
+            };
+            execute_direct_query(deps, env, info, channel_id, msg)
+        }
+        ExecuteMsg::IbcQueryMany {
+            channels,
+            msgs,
+            callback,
+            partial_after,
+            quorum,
+        } => {
+            let group = QueryGroup {
+                callback,
+                members: vec![],
+                deadline: partial_after.map(|seconds| env.block.time.plus_seconds(seconds)),
+                quorum,
+            };
+            execute_ibc_query_many(deps, env, info, channels, msgs, group)
+        }
+        ExecuteMsg::IbcQueryPaginated {
+            channel_id,
+            query,
+            callback,
+        } => execute_ibc_query_paginated(deps, env, channel_id, query, callback),
+        ExecuteMsg::DeliverQueryGroup { group_id } => {
+            execute_deliver_query_group(deps, env, group_id)
+        }
+        ExecuteMsg::Subscribe(msg) => execute_subscribe(deps, info, msg),
+        ExecuteMsg::FundSubscription { subscription_id } => {
+            execute_fund_subscription(deps, info, subscription_id)
+        }
+        ExecuteMsg::Trigger { subscription_id } => {
+            execute_trigger(deps, env, info, subscription_id)
+        }
+        ExecuteMsg::PauseSubscription { subscription_id } => {
+            execute_set_paused(deps, info, subscription_id, true)
+        }
+        ExecuteMsg::ResumeSubscription { subscription_id } => {
+            execute_set_paused(deps, info, subscription_id, false)
+        }
+        ExecuteMsg::CancelSubscription { subscription_id } => {
+            execute_cancel(deps, info, subscription_id)
+        }
+        ExecuteMsg::Flush { channel_id } => execute_flush(deps, env, channel_id),
+        ExecuteMsg::UpdateBatching {
+            channel_id,
+            flush_at,
+        } => execute_update_batching(deps, info, channel_id, flush_at),
+        ExecuteMsg::UpdateAdmin { admin } => execute_update_admin(deps, info, admin),
+        ExecuteMsg::UpdateCacheTtl { ttl } => execute_update_cache_ttl(deps, info, ttl),
+        ExecuteMsg::SetView {
+            name,
+            queries,
+            chained,
+        } => execute_set_view(deps, info, name, queries, chained),
+        ExecuteMsg::RemoveView { name } => execute_remove_view(deps, info, name),
+        ExecuteMsg::RegisterResponder { name, contract } => {
+            execute_register_responder(deps, info, name, contract)
+        }
+        ExecuteMsg::RemoveResponder { name } => execute_remove_responder(deps, info, name),
+        ExecuteMsg::SetQueryability {
+            contract,
+            queryability,
+        } => execute_set_queryability(deps, info, contract, queryability),
+        ExecuteMsg::UpdateStargateAllowlist { add, remove } => {
+            execute_update_stargate_allowlist(deps, info, add, remove)
+        }
     }
 }
 
 pub fn execute_ibc_query(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     channel_id: String,
-    msgs: Vec<QueryRequest<Empty>>,
+    msgs: Vec<QueryRequest<HostQuery>>,
     callback: String,
+    max_age: Option<u64>,
 ) -> Result<Response, ContractError> {
     // validate callback address
     deps.api.addr_validate(&callback)?;
+    if let Some(max_age) = max_age {
+        if let Some(results) = cached_results(deps.storage, &env, &channel_id, &msgs, max_age)? {
+            // no round trip, the callback runs right after this
+            let query_id = next_query_id(deps.storage)?;
+            let msg = ReceiveIbcQueryResponseMsg {
+                channel_id,
+                query_id,
+                result: IbcQueryResult::Ok(results),
+            }
+            .into_cosmos_msg(callback)?;
+            return Ok(Response::new()
+                .add_message(msg)
+                .add_attribute("action", "handle_check_remote_balance")
+                .add_attribute("query_id", query_id.to_string())
+                .add_attribute("cache_hit", "true"));
+        }
+    }
+    // someone asked the same already, share their answer
+    let hash = batch_hash(&msgs)?;
+    if let Some(packet_id) = in_flight(deps.storage, &channel_id, &hash)? {
+        let query_id = next_query_id(deps.storage)?;
+        WAITING.save(deps.storage, (packet_id, query_id), &callback)?;
+        return Ok(Response::new()
+            .add_attribute("action", "handle_check_remote_balance")
+            .add_attribute("query_id", query_id.to_string())
+            .add_attribute("coalesced_with", packet_id.to_string()));
+    }
+
+    if let Some(flush_at) = BATCHING.may_load(deps.storage, &channel_id)? {
+        // a request too large for one packet is sharded instead
+        let max_batch_size = channel_version(deps.as_ref(), &channel_id)?.max_batch_size;
+        if msgs.len() as u32 <= max_batch_size {
+            return enqueue_query(deps, &env, channel_id, msgs, callback, flush_at);
+        }
+    }
 
-    // construct a packet to send
-    let packet = PacketMsg::IbcQuery { msgs, callback };
+    let sender = info.sender.to_string();
+    if let Some(shards) = plan_shards(deps.as_ref(), &env, &channel_id, &sender, &callback, &msgs)?
+    {
+        return send_shards(deps, &env, channel_id, shards, callback);
+    }
+
+    // v1 callbacks get the raw ack, which can't be shared
+    let shared = CHANNELS
+        .may_load(deps.storage, &channel_id)?
+        .is_some_and(|channel| channel.negotiated.version != ProtocolVersion::V1);
+    let sent_on = channel_id.clone();
+    let (query_id, msg) = send_query(deps.branch(), &env, sender, channel_id, msgs, callback)?;
+    if shared {
+        track(deps.storage, &sent_on, &hash, query_id)?;
+    }
+
+    let res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "handle_check_remote_balance")
+        .add_attribute("query_id", query_id.to_string());
+    Ok(res)
+}
+
+/// Sends a packet as it is. Projected and aggregated results are only good for this caller,
+/// and chained queries only make sense together, so these skip the cache, coalescing, batching
+/// and sharding.
+pub fn execute_direct_query(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    msg: PacketMsg<HostQuery>,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(msg.callback())?;
+    let (query_id, msg) = send_packet(deps, &env, info.sender.into(), channel_id, msg)?;
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "handle_check_remote_balance")
+        .add_attribute("query_id", query_id.to_string()))
+}
+
+/// Builds the packet carrying `msgs` to `channel_id`, returning its query id
+pub(crate) fn send_query(
+    deps: DepsMut,
+    env: &Env,
+    sender: String,
+    channel_id: String,
+    msgs: Vec<QueryRequest<HostQuery>>,
+    callback: String,
+) -> Result<(u64, IbcMsg), ContractError> {
+    let msg = PacketMsg::IbcQuery {
+        msgs,
+        callback,
+        projections: vec![],
+        chained: false,
+        aggregation: None,
+    };
+    send_packet(deps, env, sender, channel_id, msg)
+}
+
+fn send_packet(
+    deps: DepsMut,
+    env: &Env,
+    sender: String,
+    channel_id: String,
+    mut msg: PacketMsg<HostQuery>,
+) -> Result<(u64, IbcMsg), ContractError> {
+    // protobuf can't tell all `None` projections from none, so neither does JSON
+    if let PacketMsg::IbcQuery { projections, .. } = &mut msg {
+        if projections.iter().all(Option::is_none) {
+            projections.clear();
+        }
+    }
+    // reject what the counterparty told us it can't answer before paying for a round trip
+    let negotiated = channel_version(deps.as_ref(), &channel_id)?;
+    match &msg {
+        PacketMsg::IbcQuery {
+            msgs,
+            projections,
+            chained,
+            aggregation,
+            ..
+        } => {
+            negotiated.supports_all(msgs)?;
+            negotiated.supports_projections(projections, msgs.len())?;
+            if *chained {
+                negotiated.supports_chain(msgs.len())?;
+            }
+            negotiated.supports_aggregation(aggregation.as_ref())?;
+        }
+        PacketMsg::View { .. } => negotiated.supports_views()?,
+        PacketMsg::Responder { .. } => negotiated.supports_responders()?,
+    }
+
+    // construct a packet to send, in the format the channel speaks
+    let query_id = next_query_id(deps.storage)?;
+    let packet = PacketMsgV2 {
+        id: query_id,
+        sender,
+        msg,
+    };
+    if negotiated.version == ProtocolVersion::Polytone {
+        // we are the only sender on the channel, so our count is the packet sequence
+        let sequence = PACKETS_SENT.update(deps.storage, &channel_id, |sent| -> StdResult<_> {
+            Ok(sent.unwrap_or_default() + 1)
+        })?;
+        PENDING_QUERIES.save(deps.storage, (&channel_id, sequence), &packet)?;
+    }
     let msg = IbcMsg::SendPacket {
+        data: negotiated.wire_format().encode_packet(&packet)?,
         channel_id,
-        data: to_binary(&packet)?,
         timeout: env
             .block
             .time
             .plus_seconds(PACKET_LIFETIME.load(deps.storage)?)
             .into(),
     };
+    Ok((query_id, msg))
+}
 
-    let res = Response::new()
-        .add_message(msg)
-        .add_attribute("action", "handle_check_remote_balance");
-    Ok(res)
+pub(crate) fn assert_admin(deps: Deps, info: &MessageInfo) -> Result<(), ContractError> {
+    if ADMIN.load(deps.storage)? != info.sender {
+        return Err(ContractError::Unauthorized);
+    }
+    Ok(())
+}
+
+pub fn execute_update_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    admin: String,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    let admin = deps.api.addr_validate(&admin)?;
+    ADMIN.save(deps.storage, &admin)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_admin")
+        .add_attribute("admin", admin))
+}
+
+pub fn execute_update_cache_ttl(
+    deps: DepsMut,
+    info: MessageInfo,
+    ttl: u64,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    CACHE_TTL.save(deps.storage, &ttl)?;
+    if ttl == 0 {
+        clear_cache(deps.storage)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_cache_ttl")
+        .add_attribute("ttl", ttl.to_string()))
+}
+
+pub fn execute_update_stargate_allowlist(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    for path in &add {
+        STARGATE_ALLOWLIST.save(deps.storage, path, &Empty {})?;
+    }
+    for path in &remove {
+        STARGATE_ALLOWLIST.remove(deps.storage, path);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_stargate_allowlist")
+        .add_attribute("added", add.len().to_string())
+        .add_attribute("removed", remove.len().to_string()))
+}
+
+#[entry_point]
+pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Admin {} => to_binary(&query_admin(deps)?),
+        QueryMsg::StargateAllowlist { start_after, limit } => {
+            to_binary(&query_stargate_allowlist(deps, start_after, limit)?)
+        }
+        QueryMsg::Views { start_after, limit } => {
+            to_binary(&query_views(deps, start_after, limit)?)
+        }
+        QueryMsg::Responders { start_after, limit } => {
+            to_binary(&query_responders(deps, start_after, limit)?)
+        }
+        QueryMsg::Queryability { contract } => to_binary(&query_queryability(deps, contract)?),
+        QueryMsg::Channel { channel_id } => to_binary(&query_channel(deps, channel_id)?),
+        QueryMsg::BatchQueue { channel_id } => to_binary(&query_batch_queue(deps, channel_id)?),
+        QueryMsg::Subscription { subscription_id } => {
+            to_binary(&query_subscription(deps, subscription_id)?)
+        }
+        QueryMsg::Subscriptions { start_after, limit } => {
+            to_binary(&query_subscriptions(deps, start_after, limit)?)
+        }
+    }
+}
+
+fn query_channel(deps: Deps, channel_id: String) -> StdResult<ChannelResponse> {
+    let info = CHANNELS.load(deps.storage, &channel_id)?;
+    Ok(ChannelResponse {
+        channel_id,
+        negotiated: info.negotiated,
+    })
+}
+
+fn query_admin(deps: Deps) -> StdResult<AdminResponse> {
+    let admin = ADMIN.load(deps.storage)?;
+    Ok(AdminResponse {
+        admin: admin.into(),
+    })
+}
+
+fn query_stargate_allowlist(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<StargateAllowlistResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let paths = STARGATE_ALLOWLIST
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(StargateAllowlistResponse { paths })
 }
 
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::testing::{
-        mock_dependencies, mock_env, mock_ibc_channel_connect_ack, mock_ibc_channel_open_init,
-        mock_ibc_channel_open_try, mock_info, MockApi, MockQuerier, MockStorage,
+        mock_dependencies, mock_env, mock_ibc_channel_connect_ack,
+        mock_ibc_channel_connect_confirm, mock_ibc_channel_open_init, mock_ibc_channel_open_try,
+        mock_ibc_packet_ack, mock_ibc_packet_timeout, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        from_binary, BankQuery, ContractResult, CosmosMsg, Empty, IbcAcknowledgement, OwnedDeps,
+        WasmMsg, WasmQuery,
     };
-    use cosmwasm_std::OwnedDeps;
 
-    use cw_ibc_query::{APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION};
+    use cw_ibc_query::{
+        check_version, AppVersion, IbcQueryResult, InterchainQueryPacketData, ProtocolVersion,
+        QueryFamily, ReceiveIbcQueryResponseMsg, ReceiverExecuteMsg, SimpleIcaError, WireFormat,
+        APP_ORDER, BAD_APP_ORDER, IBC_APP_VERSION, ICQ_VERSION, POLYTONE_VERSION,
+    };
 
-    use crate::ibc::{ibc_channel_connect, ibc_channel_open};
+    use crate::ibc::{ibc_channel_connect, ibc_channel_open, ibc_packet_ack, ibc_packet_timeout};
 
     use super::*;
 
@@ -86,6 +528,9 @@ mod tests {
         let mut deps = mock_dependencies();
         let msg = InstantiateMsg {
             packet_lifetime: 60u64,
+            admin: None,
+            max_batch_size: None,
+            cache_ttl: None,
         };
         let info = mock_info(CREATOR, &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -99,12 +544,19 @@ mod tests {
 
         let msg = InstantiateMsg {
             packet_lifetime: 60u64,
+            admin: None,
+            max_batch_size: None,
+            cache_ttl: None,
         };
         let info = mock_info("creator", &[]);
         let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
         assert_eq!(0, res.messages.len())
     }
 
+    fn version() -> String {
+        AppVersion::new(50, None).encode()
+    }
+
     #[test]
     fn enforce_version_in_handshake() {
         let mut deps = setup();
@@ -134,4 +586,394 @@ mod tests {
         let res = ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
         assert_eq!(0, res.messages.len());
     }
+
+    #[test]
+    fn enforce_json_version_in_handshake() {
+        let mut deps = setup();
+
+        let wrong_order = mock_ibc_channel_open_try("channel-12", BAD_APP_ORDER, &version());
+        ibc_channel_open(deps.as_mut(), mock_env(), wrong_order).unwrap_err();
+
+        let valid_handshake = mock_ibc_channel_open_try("channel-12", APP_ORDER, &version());
+        ibc_channel_open(deps.as_mut(), mock_env(), valid_handshake).unwrap();
+    }
+
+    #[test]
+    fn json_handshake_flow() {
+        let mut deps = setup();
+        let channel_id = "channel-1234";
+
+        let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, &version());
+        ibc_channel_open(deps.as_mut(), mock_env(), handshake_open).unwrap();
+
+        let handshake_connect = mock_ibc_channel_connect_ack(channel_id, APP_ORDER, &version());
+        let res = ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
+        assert_eq!(0, res.messages.len());
+    }
+
+    #[test]
+    fn handshake_negotiates_capabilities() {
+        let mut deps = setup();
+        let channel_id = "channel-5";
+        let theirs = AppVersion {
+            query_families: vec![QueryFamily::Bank],
+            ..AppVersion::new(5, None)
+        };
+
+        // on try we answer with the intersection
+        let handshake_open = mock_ibc_channel_open_try(channel_id, APP_ORDER, &theirs.encode());
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), handshake_open)
+            .unwrap()
+            .unwrap();
+        let negotiated = check_version(&res.version).unwrap();
+        assert_eq!(negotiated.query_families, vec![QueryFamily::Bank]);
+        assert_eq!(negotiated.max_batch_size, 5);
+
+        // and store it once the channel is open
+        let handshake_confirm =
+            mock_ibc_channel_connect_confirm(channel_id, APP_ORDER, &res.version);
+        ibc_channel_connect(deps.as_mut(), mock_env(), handshake_confirm).unwrap();
+        let query_msg = QueryMsg::Channel {
+            channel_id: channel_id.to_string(),
+        };
+        let res: ChannelResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+        assert_eq!(res.negotiated, negotiated);
+    }
+
+    #[test]
+    fn channels_from_before_the_upgrade_speak_v1() {
+        let mut deps = setup();
+        let msg = ExecuteMsg::IbcQuery {
+            channel_id: "channel-7".to_string(),
+            msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                address: "someone".to_string(),
+            })],
+            callback: "callback".to_string(),
+            max_age: None,
+            projections: vec![],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let data = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+            other => panic!("expected a packet, got {:?}", other),
+        };
+        // the plain v1 packet, without id or sender
+        let packet: PacketMsg<HostQuery> = from_binary(data).unwrap();
+        assert_eq!(packet.callback(), "callback");
+    }
+
+    #[test]
+    fn projections_of_only_none_are_left_out() {
+        let mut deps = setup();
+        // the same packet goes out as JSON or protobuf
+        let channels = [
+            ("channel-7", AppVersion::new(10, None)),
+            ("channel-8", crate::testing::json_v2(10)),
+        ];
+        for (channel_id, negotiated) in channels {
+            crate::testing::setup_channel(deps.as_mut(), channel_id, negotiated.clone());
+            let msg = ExecuteMsg::IbcQueryChain {
+                channel_id: channel_id.to_string(),
+                msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                    address: "someone".to_string(),
+                })],
+                callback: "callback".to_string(),
+                projections: vec![None],
+            };
+            let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+            let data = match &res.messages[0].msg {
+                CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data,
+                other => panic!("expected a packet, got {:?}", other),
+            };
+            let packet: PacketMsgV2<HostQuery> =
+                negotiated.wire_format().decode_packet(data).unwrap();
+            match packet.msg {
+                PacketMsg::IbcQuery { projections, .. } => assert_eq!(projections, vec![]),
+                other => panic!("unexpected packet {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn projections_are_not_answered_from_the_cache() {
+        let mut deps = setup();
+        crate::testing::setup_channel(deps.as_mut(), "channel-7", AppVersion::new(10, None));
+        let msg = ExecuteMsg::IbcQuery {
+            channel_id: "channel-7".to_string(),
+            msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                address: "someone".to_string(),
+            })],
+            callback: "callback".to_string(),
+            max_age: Some(60),
+            projections: vec![Some(cw_ibc_query::Projection::Pointer(
+                "/amount".to_string(),
+            ))],
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg);
+        assert_eq!(err.unwrap_err(), ContractError::CachedProjections);
+    }
+
+    #[test]
+    fn rejects_unsupported_queries_before_sending() {
+        let mut deps = setup();
+        let channel_id = "channel-7";
+        let bank_query = QueryRequest::Bank(BankQuery::AllBalances {
+            address: "someone".to_string(),
+        });
+        let ibc_query = |msgs| ExecuteMsg::IbcQuery {
+            channel_id: channel_id.to_string(),
+            msgs,
+            callback: "callback".to_string(),
+            max_age: None,
+            projections: vec![],
+        };
+
+        // counterparty only answers bank queries, two at a time
+        let theirs = AppVersion {
+            query_families: vec![QueryFamily::Bank],
+            stargate: false,
+            ..AppVersion::new(2, Some("other_chain"))
+        };
+        let handshake_connect =
+            mock_ibc_channel_connect_ack(channel_id, APP_ORDER, &theirs.encode());
+        ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
+
+        let wasm_query = QueryRequest::Wasm(WasmQuery::Raw {
+            contract_addr: "contract".to_string(),
+            key: Binary::default(),
+        });
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![bank_query.clone(), wasm_query]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            SimpleIcaError::UnsupportedQuery("wasm".to_string()).into()
+        );
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![cw_ibc_query::denom_trace_query("ABCD")]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            SimpleIcaError::UnsupportedQuery("stargate".to_string()).into()
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![bank_query.clone(); 3]),
+        )
+        .unwrap();
+        // too many for one packet, so they go in two
+        assert_eq!(2, res.messages.len());
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![bank_query]),
+        )
+        .unwrap();
+        assert_eq!(1, res.messages.len());
+    }
+
+    #[test]
+    fn queries_async_icq_hosts() {
+        let mut deps = setup();
+        let channel_id = "channel-9";
+
+        let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, ICQ_VERSION);
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), handshake_open)
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.version, ICQ_VERSION);
+        let handshake_connect = mock_ibc_channel_connect_ack(channel_id, APP_ORDER, ICQ_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
+
+        // hosts only answer ABCI queries
+        let ibc_query = |msgs| ExecuteMsg::IbcQuery {
+            channel_id: channel_id.to_string(),
+            msgs,
+            callback: "callback".to_string(),
+            max_age: None,
+            projections: vec![],
+        };
+        let bank_query = QueryRequest::Bank(BankQuery::AllBalances {
+            address: "someone".to_string(),
+        });
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![bank_query]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            SimpleIcaError::UnsupportedQuery("bank".to_string()).into()
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(CREATOR, &[]),
+            ibc_query(vec![cw_ibc_query::denom_trace_query("ABCD")]),
+        )
+        .unwrap();
+        let data = match &res.messages[0].msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => data.clone(),
+            _ => panic!("expected a packet"),
+        };
+        let packet: InterchainQueryPacketData = from_binary(&data).unwrap();
+        assert!(packet.memo.contains("callback"));
+
+        // the host's ack goes to the callback like any other
+        let ack = WireFormat::from(ProtocolVersion::Icq)
+            .encode_ack(0, vec![ContractResult::Ok(Binary::from(b"trace"))])
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(channel_id, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = data;
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        let callback: ReceiverExecuteMsg = match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).unwrap(),
+            _ => panic!("expected a callback"),
+        };
+        assert_eq!(
+            callback,
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: channel_id.to_string(),
+                query_id: 1,
+                result: IbcQueryResult::Ok(vec![ContractResult::Ok(Binary::from(b"trace"))]),
+            })
+        );
+    }
+
+    #[test]
+    fn queries_polytone_voices() {
+        let mut deps = setup();
+        let channel_id = "channel-9";
+
+        let handshake_open = mock_ibc_channel_open_init(channel_id, APP_ORDER, POLYTONE_VERSION);
+        ibc_channel_open(deps.as_mut(), mock_env(), handshake_open).unwrap();
+        let handshake_connect =
+            mock_ibc_channel_connect_ack(channel_id, APP_ORDER, POLYTONE_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), handshake_connect).unwrap();
+
+        for address in ["someone", "someone else"] {
+            let ibc_query = ExecuteMsg::IbcQuery {
+                channel_id: channel_id.to_string(),
+                msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                    address: address.to_string(),
+                })],
+                callback: "callback".to_string(),
+                max_age: None,
+                projections: vec![],
+            };
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(CREATOR, &[]),
+                ibc_query,
+            )
+            .unwrap();
+        }
+
+        // voices don't echo our callback, it is found by packet sequence
+        let ack = WireFormat::from(ProtocolVersion::Polytone)
+            .encode_ack(0, vec![ContractResult::Ok(Binary::from(b"{}"))])
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(channel_id, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.sequence = 2;
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        let callback: ReceiverExecuteMsg = match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).unwrap(),
+            _ => panic!("expected a callback"),
+        };
+        assert_eq!(
+            callback,
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: channel_id.to_string(),
+                query_id: 2,
+                result: IbcQueryResult::Ok(vec![ContractResult::Ok(Binary::from(b"{}"))]),
+            })
+        );
+
+        // each packet is answered once
+        ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap_err();
+        let mut msg = mock_ibc_packet_timeout(channel_id, &Empty {}).unwrap();
+        msg.packet.sequence = 1;
+        ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(!PENDING_QUERIES.has(&deps.storage, (channel_id, 1)));
+    }
+
+    #[test]
+    fn update_stargate_allowlist() {
+        let mut deps = setup();
+        let path = "/cosmos.bank.v1beta1.Query/Balance";
+        let msg = ExecuteMsg::UpdateStargateAllowlist {
+            add: vec![path.to_string()],
+            remove: vec![],
+        };
+
+        // only the admin may touch the allowlist
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let query_msg = QueryMsg::StargateAllowlist {
+            start_after: None,
+            limit: None,
+        };
+        let res: StargateAllowlistResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg.clone()).unwrap()).unwrap();
+        assert_eq!(res.paths, vec![path.to_string()]);
+
+        let msg = ExecuteMsg::UpdateStargateAllowlist {
+            add: vec![],
+            remove: vec![path.to_string()],
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let res: StargateAllowlistResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), query_msg).unwrap()).unwrap();
+        assert!(res.paths.is_empty());
+    }
+
+    #[test]
+    fn update_admin() {
+        let mut deps = setup();
+        let msg = ExecuteMsg::UpdateAdmin {
+            admin: "new_admin".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let res: AdminResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Admin {}).unwrap()).unwrap();
+        assert_eq!(res.admin, "new_admin");
+    }
 }
@@ -0,0 +1,84 @@
+//! The chain-specific custom query set this contract is built with.
+//! Build with `--features osmosis` or `--features token_factory` to run (and send)
+//! `QueryRequest::Custom` queries for that chain's bindings. Without a feature the
+//! contract only understands the standard queries.
+
+#[cfg(any(feature = "osmosis", feature = "token_factory"))]
+use cosmwasm_std::CustomQuery;
+#[cfg(not(any(feature = "osmosis", feature = "token_factory")))]
+use cosmwasm_std::Empty;
+#[cfg(any(feature = "osmosis", feature = "token_factory"))]
+use schemars::JsonSchema;
+#[cfg(any(feature = "osmosis", feature = "token_factory"))]
+use serde::{Deserialize, Serialize};
+
+#[cfg(all(feature = "osmosis", feature = "token_factory"))]
+compile_error!("features `osmosis` and `token_factory` are mutually exclusive");
+
+#[cfg(not(any(feature = "osmosis", feature = "token_factory")))]
+pub type HostQuery = Empty;
+#[cfg(not(any(feature = "osmosis", feature = "token_factory")))]
+pub const CUSTOM_QUERY_NAME: Option<&str> = None;
+
+#[cfg(feature = "osmosis")]
+pub type HostQuery = OsmosisQuery;
+#[cfg(feature = "osmosis")]
+pub const CUSTOM_QUERY_NAME: Option<&str> = Some("osmosis");
+
+#[cfg(feature = "token_factory")]
+pub type HostQuery = TokenFactoryQuery;
+#[cfg(feature = "token_factory")]
+pub const CUSTOM_QUERY_NAME: Option<&str> = Some("token_factory");
+
+/// Mirrors the JSON of the Osmosis query bindings
+#[cfg(feature = "osmosis")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OsmosisQuery {
+    FullDenom {
+        creator_addr: String,
+        subdenom: String,
+    },
+    PoolState {
+        id: u64,
+    },
+    SpotPrice {
+        swap: Swap,
+        with_swap_fee: bool,
+    },
+}
+
+#[cfg(feature = "osmosis")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Swap {
+    pub pool_id: u64,
+    pub denom_in: String,
+    pub denom_out: String,
+}
+
+#[cfg(feature = "osmosis")]
+impl CustomQuery for OsmosisQuery {}
+
+/// Mirrors the JSON of the token factory query bindings
+#[cfg(feature = "token_factory")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenFactoryQuery {
+    FullDenom {
+        creator_addr: String,
+        subdenom: String,
+    },
+    Admin {
+        denom: String,
+    },
+    Metadata {
+        denom: String,
+    },
+    DenomsByCreator {
+        creator: String,
+    },
+    Params {},
+}
+
+#[cfg(feature = "token_factory")]
+impl CustomQuery for TokenFactoryQuery {}
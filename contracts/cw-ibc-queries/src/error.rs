@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Timestamp};
 use cw_utils::{ParseReplyError, PaymentError};
 
 use cw_ibc_query::SimpleIcaError;
@@ -24,4 +24,73 @@ pub enum ContractError {
 
     #[error("Invalid reply id")]
     InvalidReplyId,
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+
+    #[error("Packet of {size} bytes exceeds the limit of {max} bytes")]
+    PacketTooLarge { size: usize, max: usize },
+
+    #[error("A query group needs at least one channel")]
+    EmptyQueryGroup,
+
+    #[error("Channel {0} appears twice in the query group")]
+    DuplicateChannel(String),
+
+    #[error("Channel {0} can't report decoded results to a query group")]
+    GroupUnsupported(String),
+
+    #[error("Unknown query group {0}")]
+    UnknownQueryGroup(u64),
+
+    #[error("Query group {0} is still waiting for its deadline")]
+    GroupDeadlineNotReached(u64),
+
+    #[error("A quorum of {threshold} can't be reached with {channels} channels")]
+    InvalidQuorum { threshold: u32, channels: usize },
+
+    #[error("Subscription interval must be at least one second")]
+    ZeroInterval,
+
+    #[error("Unknown subscription {0}")]
+    UnknownSubscription(u64),
+
+    #[error("Subscription {0} is paused")]
+    SubscriptionPaused(u64),
+
+    #[error("Subscription {0} expired")]
+    SubscriptionExpired(u64),
+
+    #[error("Subscription {id} is not due until {due}")]
+    SubscriptionNotDue { id: u64, due: Timestamp },
+
+    #[error("Subscription {0} can't pay the bounty anymore")]
+    SubscriptionUnfunded(u64),
+
+    #[error("Channel {0} can't batch queries")]
+    BatchingUnsupported(String),
+
+    #[error("Nothing is queued on channel {0}")]
+    EmptyQueue(String),
+
+    #[error("Channel {0} can't follow pages")]
+    PaginationUnsupported(String),
+
+    #[error("View {0} has no queries")]
+    EmptyView(String),
+
+    #[error("Unknown view {0}")]
+    UnknownView(String),
+
+    #[error("Invalid params for view {name}: {err}")]
+    InvalidViewParams { name: String, err: String },
+
+    #[error("Unknown responder {0}")]
+    UnknownResponder(String),
+
+    #[error("Projected results aren't cached, max_age can't be used with projections")]
+    CachedProjections,
 }
@@ -0,0 +1,410 @@
+use cosmwasm_std::{
+    CosmosMsg, DepsMut, Env, MessageInfo, Order, QueryRequest, Response, StdResult, Storage,
+};
+use cw_ibc_query::{
+    ChannelQueryResult, IbcQueryResult, ProtocolVersion, ReceiveIbcQueryGroupResponseMsg,
+};
+
+use crate::contract::send_query;
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::ibc::channel_version;
+use crate::state::{next_query_id, QueryGroup, GROUP_MEMBERS, GROUP_RESULTS, QUERY_GROUPS};
+
+/// Sends `msgs` to every channel as a packet of its own, tracked as `group`
+pub fn execute_ibc_query_many(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channels: Vec<String>,
+    msgs: Vec<QueryRequest<HostQuery>>,
+    mut group: QueryGroup,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&group.callback)?;
+    if channels.is_empty() {
+        return Err(ContractError::EmptyQueryGroup);
+    }
+    if let Some(quorum) = &group.quorum {
+        if quorum.threshold == 0 || quorum.threshold as usize > channels.len() {
+            return Err(ContractError::InvalidQuorum {
+                threshold: quorum.threshold,
+                channels: channels.len(),
+            });
+        }
+    }
+
+    for (i, channel_id) in channels.iter().enumerate() {
+        if channels[..i].contains(channel_id) {
+            return Err(ContractError::DuplicateChannel(channel_id.clone()));
+        }
+        // v1 callbacks get the raw ack, which we can't collect
+        if channel_version(deps.as_ref(), channel_id)?.version == ProtocolVersion::V1 {
+            return Err(ContractError::GroupUnsupported(channel_id.clone()));
+        }
+    }
+
+    let group_id = next_query_id(deps.storage)?;
+    let mut res = Response::new()
+        .add_attribute("action", "ibc_query_many")
+        .add_attribute("group_id", group_id.to_string());
+    for channel_id in channels {
+        let (query_id, msg) = send_query(
+            deps.branch(),
+            &env,
+            info.sender.to_string(),
+            channel_id.clone(),
+            msgs.clone(),
+            group.callback.clone(),
+        )?;
+        GROUP_MEMBERS.save(deps.storage, query_id, &group_id)?;
+        group.members.push((channel_id, query_id));
+        res = res.add_message(msg);
+    }
+
+    QUERY_GROUPS.save(deps.storage, group_id, &group)?;
+    Ok(res)
+}
+
+/// Delivers the results a group has so far, once its deadline passed
+pub fn execute_deliver_query_group(
+    deps: DepsMut,
+    env: Env,
+    group_id: u64,
+) -> Result<Response, ContractError> {
+    let group = QUERY_GROUPS
+        .may_load(deps.storage, group_id)?
+        .ok_or(ContractError::UnknownQueryGroup(group_id))?;
+    if !deadline_passed(&group, &env) {
+        return Err(ContractError::GroupDeadlineNotReached(group_id));
+    }
+
+    let msg = deliver(deps.storage, group_id, group)?;
+    Ok(Response::new()
+        .add_attribute("action", "deliver_query_group")
+        .add_attribute("group_id", group_id.to_string())
+        .add_message(msg))
+}
+
+/// Holds the result of one query of a group. Returns the group callback once every channel
+/// answered, or the deadline passed.
+pub fn record_group_result(
+    storage: &mut dyn Storage,
+    env: &Env,
+    group_id: u64,
+    query_id: u64,
+    channel_id: &str,
+    result: IbcQueryResult,
+) -> StdResult<Option<CosmosMsg>> {
+    GROUP_MEMBERS.remove(storage, query_id);
+    GROUP_RESULTS.save(storage, (group_id, channel_id), &result)?;
+
+    let group = QUERY_GROUPS.load(storage, group_id)?;
+    let answered = GROUP_RESULTS
+        .prefix(group_id)
+        .keys(storage, None, None, Order::Ascending)
+        .count();
+    if answered < group.members.len() && !deadline_passed(&group, env) {
+        return Ok(None);
+    }
+    deliver(storage, group_id, group).map(Some)
+}
+
+fn deadline_passed(group: &QueryGroup, env: &Env) -> bool {
+    group
+        .deadline
+        .is_some_and(|deadline| env.block.time >= deadline)
+}
+
+/// Builds the group callback and forgets the group. Queries still in flight are answered on
+/// their own later.
+fn deliver(storage: &mut dyn Storage, group_id: u64, group: QueryGroup) -> StdResult<CosmosMsg> {
+    let results: Vec<ChannelQueryResult> = GROUP_RESULTS
+        .prefix(group_id)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(channel_id, result)| ChannelQueryResult { channel_id, result }))
+        .collect::<StdResult<_>>()?;
+    let mut pending = vec![];
+    for (channel_id, query_id) in group.members {
+        if results
+            .iter()
+            .any(|answered| answered.channel_id == channel_id)
+        {
+            GROUP_RESULTS.remove(storage, (group_id, &channel_id));
+        } else {
+            GROUP_MEMBERS.remove(storage, query_id);
+            pending.push(channel_id);
+        }
+    }
+    QUERY_GROUPS.remove(storage, group_id);
+
+    ReceiveIbcQueryGroupResponseMsg {
+        group_id,
+        quorum: group.quorum.map(|quorum| quorum.tally(&results)),
+        results,
+        pending,
+    }
+    .into_cosmos_msg(group.callback)
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_packet_ack, mock_ibc_packet_timeout, mock_info,
+    };
+    use cosmwasm_std::{
+        from_binary, BankQuery, Binary, ContractResult, Empty, IbcAcknowledgement, IbcPacket,
+        WasmMsg,
+    };
+    use cw_ibc_query::{AppVersion, Quorum, QuorumResult, ReceiverExecuteMsg, WireFormat};
+
+    use crate::contract::execute;
+    use crate::ibc::{ibc_packet_ack, ibc_packet_timeout};
+    use crate::msg::ExecuteMsg;
+    use crate::testing::{json_v2, packets, setup, setup_channel};
+
+    use super::*;
+
+    const CREATOR: &str = "creator";
+    const CHANNELS_V2: [&str; 3] = ["channel-1", "channel-2", "channel-3"];
+
+    /// Opens the v2 channels, and channel-4 on v1
+    fn setup_channels(mut deps: DepsMut) {
+        setup(deps.branch(), CREATOR);
+        for channel_id in CHANNELS_V2 {
+            setup_channel(deps.branch(), channel_id, json_v2(10));
+        }
+        setup_channel(deps, "channel-4", AppVersion::legacy());
+    }
+
+    fn query_many(
+        channels: &[&str],
+        partial_after: Option<u64>,
+        quorum: Option<Quorum>,
+    ) -> ExecuteMsg {
+        ExecuteMsg::IbcQueryMany {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                address: "treasury".to_string(),
+            })],
+            callback: "dashboard".to_string(),
+            partial_after,
+            quorum,
+        }
+    }
+
+    fn ack(deps: DepsMut, env: Env, packet: IbcPacket, query_id: u64) -> Vec<CosmosMsg> {
+        ack_with(deps, env, packet, query_id, b"{}")
+    }
+
+    fn ack_with(
+        deps: DepsMut,
+        env: Env,
+        packet: IbcPacket,
+        query_id: u64,
+        data: &[u8],
+    ) -> Vec<CosmosMsg> {
+        let results = vec![ContractResult::Ok(Binary::from(data))];
+        let ack = WireFormat::from(ProtocolVersion::V2)
+            .encode_ack(query_id, results)
+            .unwrap();
+        let mut msg = mock_ibc_packet_ack(
+            &packet.src.channel_id,
+            &Empty {},
+            IbcAcknowledgement::new(ack),
+        )
+        .unwrap();
+        msg.original_packet = packet;
+        let res = ibc_packet_ack(deps, env, msg).unwrap();
+        res.messages.into_iter().map(|msg| msg.msg).collect()
+    }
+
+    fn callback(msgs: &[CosmosMsg]) -> ReceiverExecuteMsg {
+        match msgs {
+            [CosmosMsg::Wasm(WasmMsg::Execute { msg, .. })] => from_binary(msg).unwrap(),
+            _ => panic!("expected one callback, got {:?}", msgs),
+        }
+    }
+
+    #[test]
+    fn groups_need_distinct_v2_channels() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        let info = mock_info(CREATOR, &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            query_many(&[], None, None),
+        );
+        assert_eq!(err.unwrap_err(), ContractError::EmptyQueryGroup);
+        let msg = query_many(&["channel-1", "channel-1"], None, None);
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::DuplicateChannel("channel-1".to_string())
+        );
+        let msg = query_many(&["channel-1", "channel-4"], None, None);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::GroupUnsupported("channel-4".to_string())
+        );
+    }
+
+    #[test]
+    fn channels_without_a_version_cannot_join_groups() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        // opened before the upgrade, so it speaks v1
+        let msg = query_many(&["channel-1", "channel-5"], None, None);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::GroupUnsupported("channel-5".to_string())
+        );
+    }
+
+    #[test]
+    fn one_callback_per_group() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        // group 1 sends queries 2 and 3
+        let msg = query_many(&["channel-1", "channel-2"], None, None);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let packets = packets(&res);
+        assert_eq!(packets.len(), 2);
+
+        assert_eq!(
+            ack(deps.as_mut(), mock_env(), packets[0].clone(), 2),
+            vec![]
+        );
+        let mut timeout = mock_ibc_packet_timeout("channel-2", &Empty {}).unwrap();
+        timeout.packet = packets[1].clone();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+        let msgs: Vec<_> = res.messages.into_iter().map(|msg| msg.msg).collect();
+        let results = match callback(&msgs) {
+            ReceiverExecuteMsg::ReceiveIbcQueryGroupResponse(msg) => {
+                assert_eq!(msg.group_id, 1);
+                assert!(msg.pending.is_empty());
+                msg.results
+            }
+            other => panic!("unexpected callback {:?}", other),
+        };
+        assert_eq!(results[0].channel_id, "channel-1");
+        assert!(matches!(results[0].result, IbcQueryResult::Ok(_)));
+        assert_eq!(results[1].channel_id, "channel-2");
+        assert_eq!(results[1].result, IbcQueryResult::Timeout {});
+
+        // nothing is left behind
+        assert!(!QUERY_GROUPS.has(&deps.storage, 1));
+        assert!(!GROUP_MEMBERS.has(&deps.storage, 2) && !GROUP_MEMBERS.has(&deps.storage, 3));
+        let results = GROUP_RESULTS
+            .prefix(1)
+            .keys(&deps.storage, None, None, Order::Ascending);
+        assert_eq!(results.count(), 0);
+    }
+
+    #[test]
+    fn delivery_waits_for_the_deadline() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        let msg = query_many(&CHANNELS_V2, Some(10), None);
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let deliver = ExecuteMsg::DeliverQueryGroup { group_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), deliver);
+        assert_eq!(err.unwrap_err(), ContractError::GroupDeadlineNotReached(1));
+    }
+
+    #[test]
+    fn partial_delivery_after_deadline() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        let msg = query_many(&CHANNELS_V2, Some(10), None);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(CREATOR, &[]), msg).unwrap();
+        let packets = packets(&res);
+        assert_eq!(
+            ack(deps.as_mut(), mock_env(), packets[0].clone(), 2),
+            vec![]
+        );
+
+        // an answer after the deadline delivers what the group has
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(10);
+        let msgs = ack(deps.as_mut(), later.clone(), packets[1].clone(), 3);
+        match callback(&msgs) {
+            ReceiverExecuteMsg::ReceiveIbcQueryGroupResponse(msg) => {
+                assert_eq!(msg.results.len(), 2);
+                assert_eq!(msg.pending, vec!["channel-3".to_string()]);
+            }
+            other => panic!("unexpected callback {:?}", other),
+        }
+        let deliver = ExecuteMsg::DeliverQueryGroup { group_id: 1 };
+        let anyone = mock_info("anyone", &[]);
+        let err = execute(deps.as_mut(), later.clone(), anyone, deliver).unwrap_err();
+        assert_eq!(err, ContractError::UnknownQueryGroup(1));
+
+        // and stragglers are answered on their own
+        let msgs = ack(deps.as_mut(), later, packets[2].clone(), 4);
+        match callback(&msgs) {
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(msg) => {
+                assert_eq!((msg.channel_id.as_str(), msg.query_id), ("channel-3", 4));
+            }
+            other => panic!("unexpected callback {:?}", other),
+        }
+    }
+
+    fn with_quorum(threshold: u32) -> ExecuteMsg {
+        let quorum = Quorum {
+            threshold,
+            tolerance: None,
+        };
+        query_many(&CHANNELS_V2, None, Some(quorum))
+    }
+
+    #[test]
+    fn quorum_needs_enough_channels() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        let info = mock_info(CREATOR, &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, with_quorum(4)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidQuorum {
+                threshold: 4,
+                channels: 3
+            }
+        );
+    }
+
+    #[test]
+    fn quorum_reports_dissenters() {
+        let mut deps = mock_dependencies();
+        setup_channels(deps.as_mut());
+
+        let info = mock_info(CREATOR, &[]);
+        let res = execute(deps.as_mut(), mock_env(), info, with_quorum(2)).unwrap();
+        let packets = packets(&res);
+        let env = mock_env();
+        ack_with(deps.as_mut(), env.clone(), packets[0].clone(), 2, b"42");
+        ack_with(deps.as_mut(), env.clone(), packets[1].clone(), 3, b"41");
+        let msgs = ack_with(deps.as_mut(), env, packets[2].clone(), 4, b"42");
+        let quorum = match callback(&msgs) {
+            ReceiverExecuteMsg::ReceiveIbcQueryGroupResponse(msg) => msg.quorum.unwrap(),
+            other => panic!("unexpected callback {:?}", other),
+        };
+        assert_eq!(
+            quorum,
+            QuorumResult {
+                agreed: Some(vec![ContractResult::Ok(Binary::from(b"42"))]),
+                majority: vec!["channel-1".to_string(), "channel-3".to_string()],
+                dissenters: vec!["channel-2".to_string()],
+            }
+        );
+    }
+}
@@ -1,21 +1,37 @@
 use cosmwasm_std::{
-    entry_point, from_slice, to_binary, Binary, Deps, DepsMut, Empty, Env, Event,
+    entry_point, to_binary, Binary, ContractResult, Deps, DepsMut, Env, Event,
     Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannelCloseMsg, IbcChannelConnectMsg,
-    IbcChannelOpenMsg, IbcChannelOpenResponse, IbcPacketAckMsg, IbcPacketReceiveMsg,
-    IbcPacketTimeoutMsg, IbcReceiveResponse, QueryRequest, StdResult, SystemResult, WasmMsg,
+    IbcChannelOpenMsg, IbcChannelOpenResponse, IbcPacket, IbcPacketAckMsg, IbcPacketReceiveMsg,
+    IbcPacketTimeoutMsg, IbcReceiveResponse, Order, QueryRequest, StdResult, SystemResult, WasmMsg,
 };
 use cw_ibc_query::{
-    check_order, check_version, IbcQueryResponse, PacketMsg, ReceiveIbcResponseMsg,
-    ReceiverExecuteMsg, StdAck, IBC_APP_VERSION,
+    check_order, check_version, project, resolve_view_query, AppVersion, IbcQueryResult,
+    IbcRequestMsg, PacketMsg, PacketMsgV2, Projection, ProtocolVersion, ReceiveIbcQueryResponseMsg,
+    ReceiveIbcResponseMsg, ReceiverExecuteMsg, SimpleIcaError, WireFormat, MAX_CHAIN_STEPS,
+    MAX_PACKET_SIZE,
 };
 
+use crate::batch::{split_batch, take_batch};
+use crate::cache::cache_results;
+use crate::coalesce::release;
+use crate::custom_query::{HostQuery, CUSTOM_QUERY_NAME};
 use crate::error::ContractError;
-use crate::state::PENDING;
+use crate::group::record_group_result;
+use crate::icq::run_abci_queries;
+use crate::pagination::{follow_page, take_page};
+use crate::queryability::check_queryable;
+use crate::responder::ask_responder;
+use crate::shard::{record_shard_result, take_shard};
+use crate::state::{
+    ChannelInfo, CHANNELS, CONNECTIONS, GROUP_MEMBERS, MAX_BATCH_SIZE, PENDING, PENDING_QUERIES,
+    STARGATE_ALLOWLIST,
+};
+use crate::view::resolve_view;
 
 #[entry_point]
 /// enforces ordering and versioing constraints
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelOpenMsg,
 ) -> Result<IbcChannelOpenResponse, ContractError> {
@@ -24,32 +40,76 @@ pub fn ibc_channel_open(
     check_order(&channel.order)?;
     // In ibcv3 we don't check the version string passed in the message
     // and only check the counterparty version.
-    if let Some(counter_version) = msg.counterparty_version() {
-        check_version(counter_version)?;
-    }
+    let ours = our_version(deps.as_ref())?;
+    let version = match msg.counterparty_version() {
+        // on try we answer with what both sides support
+        Some(counter_version) => ours.negotiate(&check_version(counter_version)?)?,
+        // on init we honour a valid version proposed by the relayer, so channels to legacy
+        // counterparties can be opened with the plain `simple-ica-v1`, else we offer everything
+        None => match check_version(&channel.version) {
+            Ok(proposed) => ours.negotiate(&proposed)?,
+            Err(_) => ours,
+        },
+    };
 
     // We return the version we need (which could be different than the counterparty version)
     Ok(Some(Ibc3ChannelOpenResponse {
-        version: IBC_APP_VERSION.to_string(),
+        version: version.encode(),
     }))
 }
 
+/// The capabilities we offer on new channels. Stargate queries only once the admin allowed a
+/// path, while views and responders may well be registered after the channel opened.
+fn our_version(deps: Deps) -> StdResult<AppVersion> {
+    let stargate = STARGATE_ALLOWLIST
+        .keys(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    Ok(AppVersion {
+        stargate,
+        ..AppVersion::new(MAX_BATCH_SIZE.load(deps.storage)?, CUSTOM_QUERY_NAME)
+    })
+}
+
+/// The negotiated version of a channel. Channels opened before we recorded it can only be
+/// legacy ones.
+pub fn channel_version(deps: Deps, channel_id: &str) -> StdResult<AppVersion> {
+    Ok(CHANNELS
+        .may_load(deps.storage, channel_id)?
+        .map(|info| info.negotiated)
+        .unwrap_or_else(AppVersion::legacy))
+}
+
+/// The wire format of a channel, see `channel_version`
+pub fn channel_format(deps: Deps, channel_id: &str) -> StdResult<WireFormat> {
+    Ok(channel_version(deps, channel_id)?.wire_format())
+}
+
 #[entry_point]
 /// once it's established, we create the reflect contract
 pub fn ibc_channel_connect(
     deps: DepsMut,
     _env: Env,
     msg: IbcChannelConnectMsg,
-) -> StdResult<IbcBasicResponse> {
+) -> Result<IbcBasicResponse, ContractError> {
     let channel = msg.channel();
     let chan_id = &channel.endpoint.channel_id;
 
+    // on ack the counterparty sends what it negotiated in open try,
+    // on confirm the channel version is what we negotiated ourselves
+    let version = msg.counterparty_version().unwrap_or(&channel.version);
+    let negotiated = our_version(deps.as_ref())?.negotiate(&check_version(version)?)?;
+    let protocol = negotiated.version;
+    CHANNELS.save(deps.storage, chan_id, &ChannelInfo { negotiated })?;
+    CONNECTIONS.save(deps.storage, chan_id, &channel.connection_id)?;
+
     // store the channel id for the reply handler
     PENDING.save(deps.storage, chan_id)?;
 
     Ok(IbcBasicResponse::new()
         .add_attribute("action", "ibc_connect")
         .add_attribute("channel_id", chan_id)
+        .add_attribute("protocol", protocol.as_str())
         .add_event(Event::new("ibc").add_attribute("channel", "connect")))
 }
 
@@ -64,67 +124,268 @@ pub fn ibc_channel_close(
     let channel = msg.channel();
     // get contract address and remove lookup
     let channel_id = channel.endpoint.channel_id.as_str();
+    // we keep the channel info, in-flight packets still time out in its protocol version
 
     Ok(IbcBasicResponse::new()
         .add_attribute("action", "ibc_close")
         .add_attribute("channel_id", channel_id))
 }
 
+/// Never fails, anything that goes wrong is reported to the requester in an error ack
 #[entry_point]
 pub fn ibc_packet_receive(
     deps: DepsMut,
     _env: Env,
     msg: IbcPacketReceiveMsg,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let msg: PacketMsg = from_slice(&msg.packet.data)?;
-    match msg {
-        PacketMsg::IbcQuery { msgs, .. } => receive_query(deps.as_ref(), msgs),
+    let (format, res) = match channel_format(deps.as_ref(), &msg.packet.dest.channel_id) {
+        Ok(format) => (
+            format,
+            do_ibc_packet_receive(deps.as_ref(), format, &msg.packet),
+        ),
+        // without the channel's format, answer in the one of channels that have none recorded
+        Err(err) => (AppVersion::legacy().wire_format(), Err(err.into())),
+    };
+    match res {
+        Ok(res) => Ok(res),
+        Err(err) => Ok(IbcReceiveResponse::new()
+            .set_ack(format.encode_error(&err))
+            .add_attribute("action", "receive_ibc_query")
+            .add_attribute("error", err.to_string())),
+    }
+}
+
+fn do_ibc_packet_receive(
+    deps: Deps,
+    format: WireFormat,
+    packet: &IbcPacket,
+) -> Result<IbcReceiveResponse, ContractError> {
+    if packet.data.len() > MAX_PACKET_SIZE {
+        return Err(ContractError::PacketTooLarge {
+            size: packet.data.len(),
+            max: MAX_PACKET_SIZE,
+        });
+    }
+    let channel_id = packet.dest.channel_id.clone();
+    // this also catches packet variants from newer versions we don't know yet
+    let packet: PacketMsgV2<HostQuery> = format
+        .decode_packet(&packet.data)
+        .map_err(|err| ContractError::InvalidPacket(err.to_string()))?;
+    match &packet.msg {
+        PacketMsg::IbcQuery {
+            msgs,
+            projections,
+            chained,
+            aggregation,
+            ..
+        } => {
+            let max_batch_size = MAX_BATCH_SIZE.load(deps.storage)?;
+            if msgs.len() > max_batch_size as usize {
+                return Err(SimpleIcaError::BatchTooLarge(max_batch_size).into());
+            }
+            if *chained && msgs.len() > MAX_CHAIN_STEPS as usize {
+                return Err(SimpleIcaError::ChainTooLong(MAX_CHAIN_STEPS).into());
+            }
+            if !projections.is_empty() && projections.len() != msgs.len() {
+                return Err(SimpleIcaError::ProjectionMismatch {
+                    queries: msgs.len(),
+                    projections: projections.len(),
+                }
+                .into());
+            }
+            projections
+                .iter()
+                .flatten()
+                .try_for_each(Projection::validate)?;
+            if let Some(aggregation) = aggregation {
+                aggregation.validate()?;
+            }
+            receive_query(deps, format, &channel_id, packet)
+        }
+        PacketMsg::View { .. } => receive_query(deps, format, &channel_id, packet),
+        PacketMsg::Responder { name, request, .. } => {
+            let request = IbcRequestMsg {
+                channel_id,
+                sender: packet.sender.clone(),
+                request: request.clone(),
+            };
+            let result = ask_responder(deps, name, request)?;
+            let acknowledgement = format.encode_ack(packet.id, vec![result])?;
+            Ok(IbcReceiveResponse::new()
+                .set_ack(acknowledgement)
+                .add_attribute("action", "receive_ibc_query")
+                .add_attribute("responder", name))
+        }
     }
 }
 
 // Processes IBC query
 pub fn receive_query(
     deps: Deps,
-    msgs: Vec<QueryRequest<Empty>>,
+    format: WireFormat,
+    channel_id: &str,
+    packet: PacketMsgV2<HostQuery>,
 ) -> Result<IbcReceiveResponse, ContractError> {
-    let mut results: Vec<Binary> = vec![];
-
-    for query in msgs {
-        let res = match deps.querier.raw_query(&to_binary(&query)?) {
-            SystemResult::Ok(res) => res,
-            SystemResult::Err(err) => cosmwasm_std::ContractResult::Err(err.to_string()),
-        };
-        results.push(to_binary(&res)?);
-    }
-    let response = IbcQueryResponse { results };
+    let (msgs, projections, chained, aggregation, params) = match packet.msg {
+        PacketMsg::IbcQuery {
+            msgs,
+            projections,
+            chained,
+            aggregation,
+            ..
+        } => (msgs, projections, chained, aggregation, vec![]),
+        PacketMsg::View { name, params, .. } => {
+            let view = resolve_view(deps, &name, &params)?;
+            (view.queries, vec![], view.chained, None, params)
+        }
+        PacketMsg::Responder { .. } => {
+            return Err(ContractError::InvalidPacket(
+                "responder requests carry no queries".to_string(),
+            ))
+        }
+    };
+    let results = match format.version {
+        ProtocolVersion::Icq => run_abci_queries(deps, channel_id, msgs)?,
+        _ if chained => project(run_chain(deps, channel_id, msgs, &params)?, &projections),
+        _ => project(run_queries(deps, channel_id, msgs)?, &projections),
+    };
+    // an aggregated packet is answered with the aggregate only
+    let results = match aggregation {
+        Some(aggregation) => vec![aggregation.apply(&results)],
+        None => results,
+    };
 
-    let acknowledgement = StdAck::success(&response);
+    let acknowledgement = format.encode_ack(packet.id, results)?;
     Ok(IbcReceiveResponse::new()
         .set_ack(acknowledgement)
         .add_attribute("action", "receive_ibc_query"))
 }
 
+/// Runs each query against our chain for a packet from `channel_id`, a failing query doesn't
+/// stop the others
+pub fn run_queries(
+    deps: Deps,
+    channel_id: &str,
+    msgs: Vec<QueryRequest<HostQuery>>,
+) -> StdResult<Vec<ContractResult<Binary>>> {
+    msgs.iter()
+        .map(|query| run_query(deps, channel_id, query))
+        .collect()
+}
+
+/// Runs the queries in order, filling in their placeholders with a view's params and earlier
+/// results. A query whose placeholders can't be filled fails, and so does every query depending on it.
+pub fn run_chain(
+    deps: Deps,
+    channel_id: &str,
+    msgs: Vec<QueryRequest<HostQuery>>,
+    params: &[(String, String)],
+) -> StdResult<Vec<ContractResult<Binary>>> {
+    let mut results = Vec::with_capacity(msgs.len());
+    for query in &msgs {
+        let result = match resolve_view_query(query, params, &results) {
+            Ok(query) => run_query(deps, channel_id, &query)?,
+            Err(err) => ContractResult::Err(err),
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+fn run_query(
+    deps: Deps,
+    channel_id: &str,
+    query: &QueryRequest<HostQuery>,
+) -> StdResult<ContractResult<Binary>> {
+    // contracts may have opted out of being queried by some or all counterparties
+    if let Some(err) = check_queryable(deps, channel_id, query)? {
+        return Ok(ContractResult::Err(err));
+    }
+    match query {
+        // Stargate queries can reach any gRPC endpoint, so only run the ones the admin allowed
+        QueryRequest::Stargate { path, .. } if !STARGATE_ALLOWLIST.has(deps.storage, path) => Ok(
+            ContractResult::Err(format!("Stargate path {} is not allowed", path)),
+        ),
+        _ => Ok(match deps.querier.raw_query(&to_binary(query)?) {
+            SystemResult::Ok(res) => res,
+            SystemResult::Err(err) => ContractResult::Err(err.to_string()),
+        }),
+    }
+}
+
 #[entry_point]
 pub fn ibc_packet_ack(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     msg: IbcPacketAckMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     // we need to parse the ack based on our request
-    let original_packet: PacketMsg = from_slice(&msg.original_packet.data)?;
+    let channel_id = msg.original_packet.src.channel_id.clone();
+    let format = channel_format(deps.as_ref(), &channel_id)?;
+
+    // v1 callbacks get the raw ack, as they always did
+    if format.version == ProtocolVersion::V1 {
+        let original_packet: PacketMsgV2<HostQuery> =
+            format.decode_packet(&msg.original_packet.data)?;
+        let callback = original_packet.msg.callback().to_string();
+        return acknowledge_query(deps, env, callback, msg);
+    }
 
-    match original_packet {
-        PacketMsg::IbcQuery { callback, .. } => acknowledge_query(deps, env, callback, msg),
+    let packet = original_packet(deps.branch(), format, &msg.original_packet)?;
+    // a garbled ack is still an answer the caller should hear about
+    let result = format
+        .decode_ack(&msg.acknowledgement.data)
+        .unwrap_or_else(|err| IbcQueryResult::Error(err.to_string()));
+    // projected and aggregated results are only good for whoever asked, and chained queries
+    // hash with their placeholders
+    if let PacketMsg::IbcQuery {
+        msgs,
+        projections,
+        chained: false,
+        aggregation: None,
+        ..
+    } = &packet.msg
+    {
+        if projections.is_empty() {
+            cache_results(deps.storage, &env, &channel_id, msgs, &result)?;
+        }
     }
+    deliver_result(deps, &env, channel_id, packet, result)
 }
 
 #[entry_point]
 pub fn ibc_packet_timeout(
-    _deps: DepsMut,
-    _env: Env,
-    _msg: IbcPacketTimeoutMsg,
-) -> StdResult<IbcBasicResponse> {
-    Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"))
+    mut deps: DepsMut,
+    env: Env,
+    msg: IbcPacketTimeoutMsg,
+) -> Result<IbcBasicResponse, ContractError> {
+    let channel_id = msg.packet.src.channel_id.clone();
+    let format = channel_format(deps.as_ref(), &channel_id)?;
+    // v1 callbacks never heard about timeouts
+    if format.version == ProtocolVersion::V1 {
+        return Ok(IbcBasicResponse::new().add_attribute("action", "ibc_packet_timeout"));
+    }
+
+    let packet = original_packet(deps.branch(), format, &msg.packet)?;
+    let result = IbcQueryResult::Timeout {};
+    Ok(deliver_result(deps, &env, channel_id, packet, result)?
+        .add_attribute("action", "ibc_packet_timeout"))
+}
+
+/// The packet we sent. Most protocols carry our query id and callback, for Polytone we
+/// stored the packet when sending.
+fn original_packet(
+    deps: DepsMut,
+    format: WireFormat,
+    packet: &IbcPacket,
+) -> Result<PacketMsgV2<HostQuery>, ContractError> {
+    if format.version == ProtocolVersion::Polytone {
+        let key = (packet.src.channel_id.as_str(), packet.sequence);
+        let original = PENDING_QUERIES.load(deps.storage, key)?;
+        PENDING_QUERIES.remove(deps.storage, key);
+        return Ok(original);
+    }
+    Ok(format.decode_packet(&packet.data)?)
 }
 
 fn acknowledge_query(
@@ -147,28 +408,177 @@ fn acknowledge_query(
         .add_message(msg))
 }
 
+/// Hands the result of one of our packets to everyone who asked for it
+fn deliver_result(
+    deps: DepsMut,
+    env: &Env,
+    channel_id: String,
+    packet: PacketMsgV2<HostQuery>,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    let callback = packet.msg.callback().to_string();
+    // only plain queries are coalesced
+    let waiting = match &packet.msg {
+        PacketMsg::IbcQuery { msgs, .. } => release(deps.storage, &channel_id, msgs, packet.id)?,
+        PacketMsg::View { .. } | PacketMsg::Responder { .. } => vec![],
+    };
+    let res = if let Some(parts) = take_batch(deps.storage, packet.id)? {
+        IbcBasicResponse::new()
+            .add_attribute("action", "acknowledge_ibc_query")
+            .add_attribute("query_id", packet.id.to_string())
+            .add_messages(split_batch(parts, &channel_id, &result)?)
+    } else if let Some(query_id) = take_shard(deps.storage, packet.id)? {
+        match record_shard_result(deps.storage, query_id, packet.id, result.clone())? {
+            Some((callback, whole)) => {
+                send_query_result(callback, channel_id.clone(), query_id, whole)?
+            }
+            None => IbcBasicResponse::new()
+                .add_attribute("action", "acknowledge_ibc_query")
+                .add_attribute("query_id", query_id.to_string())
+                .add_attribute("shard_id", packet.id.to_string()),
+        }
+    } else if let Some(query_id) = take_page(deps.storage, packet.id)? {
+        follow_page(deps, env, query_id, result.clone())?
+    } else {
+        route_query_result(
+            deps,
+            env,
+            callback,
+            channel_id.clone(),
+            packet.id,
+            result.clone(),
+        )?
+    };
+    notify_waiting(res, waiting, channel_id, result)
+}
+
+/// Queries sent as part of a group wait for the rest of it, others go to their callback
+fn route_query_result(
+    deps: DepsMut,
+    env: &Env,
+    callback: String,
+    channel_id: String,
+    query_id: u64,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    let group_id = match GROUP_MEMBERS.may_load(deps.storage, query_id)? {
+        Some(group_id) => group_id,
+        None => return send_query_result(callback, channel_id, query_id, result),
+    };
+    let res = IbcBasicResponse::new()
+        .add_attribute("action", "acknowledge_ibc_query")
+        .add_attribute("query_id", query_id.to_string())
+        .add_attribute("group_id", group_id.to_string());
+    let group_msg =
+        record_group_result(deps.storage, env, group_id, query_id, &channel_id, result)?;
+    Ok(match group_msg {
+        Some(msg) => res
+            .add_attribute("callback_address", callback)
+            .add_message(msg),
+        None => res,
+    })
+}
+
+/// Sends the result of a shared packet to the callers that waited for it as well
+fn notify_waiting(
+    mut res: IbcBasicResponse,
+    waiting: Vec<(u64, String)>,
+    channel_id: String,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    for (query_id, callback) in waiting {
+        let msg = ReceiveIbcQueryResponseMsg {
+            channel_id: channel_id.clone(),
+            query_id,
+            result: result.clone(),
+        }
+        .into_cosmos_msg(callback)?;
+        res = res.add_message(msg);
+    }
+    Ok(res)
+}
+
+/// Sends the decoded results of a v2 query to the callback contract
+pub(crate) fn send_query_result(
+    callback: String,
+    channel_id: String,
+    query_id: u64,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    let msg = ReceiveIbcQueryResponseMsg {
+        channel_id,
+        query_id,
+        result,
+    }
+    .into_cosmos_msg(callback.clone())?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "acknowledge_ibc_query")
+        .add_attribute("callback_address", callback)
+        .add_attribute("query_id", query_id.to_string())
+        .add_message(msg))
+}
+
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::{
-        testing::{mock_dependencies, mock_env, mock_ibc_packet_ack},
-        BankQuery, IbcAcknowledgement,
+        coin, coins, from_binary, from_slice,
+        testing::{
+            mock_dependencies, mock_dependencies_with_balances, mock_env,
+            mock_ibc_channel_open_try, mock_ibc_packet_ack, mock_ibc_packet_recv,
+            mock_ibc_packet_timeout,
+        },
+        BalanceResponse, BankQuery, CosmosMsg, Empty, IbcAcknowledgement, Storage,
+    };
+    use cw_ibc_query::{
+        bank_balance_query, decode_stargate_response, AggregateOp, AggregateResult, Aggregation,
+        IbcQueryResponse, InputError, PolytoneCallback, QueryAck, QueryBalanceResponse, StdAck,
+        APP_ORDER, BANK_BALANCE_PATH, ICQ_VERSION, POLYTONE_VERSION,
     };
 
     use crate::msg::InstantiateMsg;
+    use crate::testing::{json_v2, setup_channel};
 
     use super::*;
 
     const CHANNEL: &str = "channel-42";
 
+    fn balance_packet() -> PacketMsgV2 {
+        PacketMsgV2 {
+            id: 3,
+            sender: "sender".to_string(),
+            msg: PacketMsg::IbcQuery {
+                msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                    address: String::from("test"),
+                })],
+                callback: "callback".to_string(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        }
+    }
+
     #[test]
     fn try_receive_query() {
         let deps = mock_dependencies();
 
         let res = receive_query(
             deps.as_ref(),
-            vec![QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
-                address: String::from("test"),
-            })],
+            ProtocolVersion::V1.into(),
+            CHANNEL,
+            PacketMsgV2 {
+                id: 0,
+                sender: String::new(),
+                msg: PacketMsg::IbcQuery {
+                    msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                        address: String::from("test"),
+                    })],
+                    callback: "callback".to_string(),
+                    projections: vec![],
+                    chained: false,
+                    aggregation: None,
+                },
+            },
         );
         assert!(res.is_ok());
     }
@@ -183,6 +593,9 @@ mod tests {
             CHANNEL,
             &InstantiateMsg {
                 packet_lifetime: 60u64,
+                admin: None,
+                max_batch_size: None,
+                cache_ttl: None,
             },
             ack,
         )
@@ -190,4 +603,446 @@ mod tests {
         let res = acknowledge_query(deps.as_mut(), env, String::from("test"), ibc_res);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn offers_stargate_once_a_path_is_allowed() {
+        let mut deps = mock_dependencies();
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+        let open = |deps: DepsMut| {
+            let theirs = AppVersion::new(10, None).encode();
+            let msg = mock_ibc_channel_open_try(CHANNEL, APP_ORDER, &theirs);
+            let res = ibc_channel_open(deps, mock_env(), msg).unwrap().unwrap();
+            check_version(&res.version).unwrap().stargate
+        };
+        assert!(!open(deps.as_mut()));
+
+        STARGATE_ALLOWLIST
+            .save(&mut deps.storage, BANK_BALANCE_PATH, &Empty {})
+            .unwrap();
+        assert!(open(deps.as_mut()));
+    }
+
+    #[test]
+    fn receive_answers_in_channel_version() {
+        let mut deps = mock_dependencies();
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+        let packet = balance_packet();
+
+        // legacy channel, plain PacketMsg in and IbcQueryResponse out
+        setup_channel(deps.as_mut(), CHANNEL, AppVersion::legacy());
+        let msg = mock_ibc_packet_recv(CHANNEL, &packet.msg).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        let response: IbcQueryResponse = ack.unwrap_into();
+        assert_eq!(response.results.len(), 1);
+
+        // v2 channel, the id comes back with structured results
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+        let msg = mock_ibc_packet_recv(CHANNEL, &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: QueryAck = from_slice(&res.acknowledgement).unwrap();
+        let response = ack.unwrap();
+        assert_eq!(response.id, 3);
+        assert!(matches!(response.results[0], ContractResult::Ok(_)));
+
+        // protobuf channel
+        let version = AppVersion::new(10, None);
+        let format = version.wire_format();
+        setup_channel(deps.as_mut(), CHANNEL, version);
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = format.encode_packet(&packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        match format.decode_ack(&res.acknowledgement).unwrap() {
+            IbcQueryResult::Ok(results) => assert!(matches!(results[0], ContractResult::Ok(_))),
+            other => panic!("unexpected ack {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hosts_apply_projections() {
+        let mut deps = mock_dependencies_with_balances(&[("test", &coins(123, "ujuno"))]);
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+        let version = AppVersion::new(10, None);
+        let format = version.wire_format();
+        setup_channel(deps.as_mut(), CHANNEL, version);
+
+        let mut packet = balance_packet();
+        let PacketMsg::IbcQuery {
+            msgs, projections, ..
+        } = &mut packet.msg
+        else {
+            unreachable!()
+        };
+        *msgs = vec![msgs[0].clone(); 3];
+        *projections = vec![
+            Some(Projection::Pointer("/amount/0/amount".to_string())),
+            Some(Projection::Pointer("/amount/0/owner".to_string())),
+            None,
+        ];
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = format.encode_packet(&packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let results = match format.decode_ack(&res.acknowledgement).unwrap() {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        assert_eq!(results[0], ContractResult::Ok(Binary::from(br#""123""#)));
+        assert_eq!(
+            results[1],
+            ContractResult::Err("Path /amount/0/owner not found in result".to_string())
+        );
+        assert!(results[2].is_ok());
+
+        // a projection for every query, or none
+        let PacketMsg::IbcQuery { projections, .. } = &mut packet.msg else {
+            unreachable!()
+        };
+        projections.truncate(1);
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+        let err = receive_error(deps.as_mut(), to_binary(&packet).unwrap());
+        assert_eq!(err, "simple-ica-v2: Got 1 projections for 3 queries");
+    }
+
+    #[test]
+    fn hosts_run_chained_queries_in_order() {
+        let mut deps = mock_dependencies_with_balances(&[("test", &coins(123, "ujuno"))]);
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+
+        let balance = |address: &str, denom: &str| {
+            QueryRequest::Bank(BankQuery::Balance {
+                address: address.to_string(),
+                denom: denom.to_string(),
+            })
+        };
+        let mut packet = balance_packet();
+        let PacketMsg::IbcQuery { msgs, chained, .. } = &mut packet.msg else {
+            unreachable!()
+        };
+        *msgs = vec![
+            balance("test", "ujuno"),
+            balance("test", "${0/amount/denom}"),
+            balance("${1/amount/owner}", "ujuno"),
+        ];
+        *chained = true;
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = to_binary(&packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let results = match WireFormat::from(ProtocolVersion::V2)
+            .decode_ack(&res.acknowledgement)
+            .unwrap()
+        {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        assert_eq!(results[0], results[1]);
+        assert!(results[1].is_ok());
+        assert_eq!(
+            results[2],
+            ContractResult::Err("Path /amount/owner not found in result of query 1".to_string())
+        );
+
+        // the host caps how long a chain may be
+        let PacketMsg::IbcQuery { msgs, .. } = &mut packet.msg else {
+            unreachable!()
+        };
+        *msgs = vec![balance("test", "ujuno"); MAX_CHAIN_STEPS as usize + 1];
+        let err = receive_error(deps.as_mut(), to_binary(&packet).unwrap());
+        assert_eq!(
+            err,
+            "simple-ica-v2: Counterparty runs at most 8 chained queries per packet"
+        );
+    }
+
+    #[test]
+    fn hosts_answer_with_the_aggregate() {
+        let mut deps = mock_dependencies_with_balances(&[
+            ("alice", &coins(123, "ujuno")),
+            ("bob", &coins(77, "ujuno")),
+        ]);
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+
+        let balance = |address: &str| {
+            QueryRequest::Bank(BankQuery::Balance {
+                address: address.to_string(),
+                denom: "ujuno".to_string(),
+            })
+        };
+        let mut packet = balance_packet();
+        let PacketMsg::IbcQuery {
+            msgs, aggregation, ..
+        } = &mut packet.msg
+        else {
+            unreachable!()
+        };
+        *msgs = vec![
+            balance("alice"),
+            QueryRequest::Stargate {
+                path: "/cosmos.bank.v1beta1.Query/Balance".to_string(),
+                data: Binary::default(),
+            },
+            balance("bob"),
+        ];
+        *aggregation = Some(Aggregation {
+            op: AggregateOp::Sum,
+            pointer: "/amount/amount".to_string(),
+        });
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = to_binary(&packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let results = match WireFormat::from(ProtocolVersion::V2)
+            .decode_ack(&res.acknowledgement)
+            .unwrap()
+        {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        assert_eq!(results.len(), 1);
+        let aggregate: AggregateResult = from_binary(&results[0].clone().unwrap()).unwrap();
+        assert_eq!(
+            aggregate,
+            AggregateResult {
+                value: Some("200".to_string()),
+                inputs: 2,
+                errors: vec![InputError {
+                    index: 1,
+                    error: "Stargate path /cosmos.bank.v1beta1.Query/Balance is not allowed"
+                        .to_string(),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn v2_ack_and_timeout_send_decoded_callback() {
+        let mut deps = mock_dependencies();
+        let version = AppVersion::new(10, None);
+        let format = version.wire_format();
+        setup_channel(deps.as_mut(), CHANNEL, version);
+        let packet = balance_packet();
+        let results = vec![ContractResult::Ok(Binary::from(b"{}"))];
+
+        let ack = format.encode_ack(packet.id, results.clone()).unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(CHANNEL, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = format.encode_packet(&packet).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            callback_msg(&res.messages[0].msg),
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: CHANNEL.to_string(),
+                query_id: 3,
+                result: IbcQueryResult::Ok(results),
+            })
+        );
+
+        let mut msg = mock_ibc_packet_timeout(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = format.encode_packet(&packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            callback_msg(&res.messages[0].msg),
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: CHANNEL.to_string(),
+                query_id: 3,
+                result: IbcQueryResult::Timeout {},
+            })
+        );
+    }
+
+    fn callback_msg(msg: &CosmosMsg) -> ReceiverExecuteMsg {
+        match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).unwrap(),
+            _ => panic!("expected a callback"),
+        }
+    }
+
+    #[test]
+    fn stargate_query_requires_allowlist() {
+        let mut deps = mock_dependencies();
+        let query = cw_ibc_query::denom_trace_query("ABCD");
+
+        let results = run_queries(deps.as_ref(), CHANNEL, vec![query.clone()]).unwrap();
+        assert_eq!(
+            results[0].clone().unwrap_err(),
+            format!(
+                "Stargate path {} is not allowed",
+                cw_ibc_query::DENOM_TRACE_PATH
+            )
+        );
+
+        // once allowed, it is passed on to the querier (the mock doesn't support stargate)
+        STARGATE_ALLOWLIST
+            .save(&mut deps.storage, cw_ibc_query::DENOM_TRACE_PATH, &Empty {})
+            .unwrap();
+        let results = run_queries(deps.as_ref(), CHANNEL, vec![query]).unwrap();
+        assert!(results[0]
+            .clone()
+            .unwrap_err()
+            .contains("Unsupported query type: Stargate"));
+    }
+
+    fn receive_error(deps: DepsMut, data: Binary) -> String {
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = data;
+        let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+        let ack: StdAck = from_slice(&res.acknowledgement).unwrap();
+        ack.unwrap_err()
+    }
+
+    #[test]
+    fn bad_packets_get_error_acks() {
+        let mut deps = mock_dependencies();
+        MAX_BATCH_SIZE.save(&mut deps.storage, &2).unwrap();
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(2));
+
+        let err = receive_error(deps.as_mut(), Binary::from(b"\xde\xad\xbe\xef"));
+        assert!(
+            err.starts_with("simple-ica-v2: Invalid packet: "),
+            "{}",
+            err
+        );
+
+        // a variant from some future version
+        let data = br#"{"id":1,"sender":"foo","msg":{"ibc_teleport":{}}}"#;
+        let err = receive_error(deps.as_mut(), Binary::from(data.to_vec()));
+        assert!(
+            err.starts_with("simple-ica-v2: Invalid packet: "),
+            "{}",
+            err
+        );
+        assert!(err.contains("ibc_teleport"), "{}", err);
+
+        let err = receive_error(deps.as_mut(), Binary(vec![b' '; MAX_PACKET_SIZE + 1]));
+        assert_eq!(
+            err,
+            format!(
+                "simple-ica-v2: Packet of {} bytes exceeds the limit of {} bytes",
+                MAX_PACKET_SIZE + 1,
+                MAX_PACKET_SIZE
+            )
+        );
+
+        let mut packet = balance_packet();
+        let PacketMsg::IbcQuery { msgs, .. } = &mut packet.msg else {
+            unreachable!()
+        };
+        *msgs = vec![msgs[0].clone(); 3];
+        let err = receive_error(deps.as_mut(), to_binary(&packet).unwrap());
+        assert_eq!(
+            err,
+            "simple-ica-v2: Counterparty accepts at most 2 queries per packet"
+        );
+
+        // v1 channels get the same treatment, tagged with their version
+        setup_channel(deps.as_mut(), CHANNEL, AppVersion::legacy());
+        let err = receive_error(deps.as_mut(), Binary::from(b"{}"));
+        assert!(
+            err.starts_with("simple-ica-v1: Invalid packet: "),
+            "{}",
+            err
+        );
+
+        // so do channels whose record can't be read
+        deps.storage.set(&CHANNELS.key(CHANNEL), b"{");
+        let err = receive_error(deps.as_mut(), to_binary(&balance_packet()).unwrap());
+        assert!(err.starts_with("simple-ica-v1: "), "{}", err);
+    }
+
+    #[test]
+    fn serves_async_icq_controllers() {
+        let mut deps = mock_dependencies_with_balances(&[("alice", &coins(123, "uatom"))]);
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+
+        let handshake_open = mock_ibc_channel_open_try(CHANNEL, APP_ORDER, ICQ_VERSION);
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), handshake_open)
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.version, ICQ_VERSION);
+        setup_channel(deps.as_mut(), CHANNEL, AppVersion::icq());
+
+        let format = WireFormat::from(ProtocolVersion::Icq);
+        let packet = PacketMsgV2::<Empty> {
+            id: 0,
+            sender: String::new(),
+            msg: PacketMsg::IbcQuery {
+                msgs: vec![
+                    bank_balance_query("alice", "uatom"),
+                    cw_ibc_query::denom_trace_query("ABCD"),
+                ],
+                callback: String::new(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        };
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = format.encode_packet(&packet).unwrap();
+
+        // nothing is answered before the admin allows it
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg.clone()).unwrap();
+        let results = match format.decode_ack(&res.acknowledgement).unwrap() {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        // errors come back with the ABCI code appended
+        assert_eq!(
+            results[0],
+            ContractResult::Err(format!(
+                "Stargate path {} is not allowed (code 1)",
+                BANK_BALANCE_PATH
+            ))
+        );
+
+        // bank balances are answered with the native bank query, in protobuf
+        STARGATE_ALLOWLIST
+            .save(&mut deps.storage, BANK_BALANCE_PATH, &Empty {})
+            .unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let results = match format.decode_ack(&res.acknowledgement).unwrap() {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        let balance: QueryBalanceResponse =
+            decode_stargate_response(&results[0].clone().unwrap()).unwrap();
+        let balance = balance.balance.unwrap();
+        assert_eq!(
+            (balance.denom.as_str(), balance.amount.as_str()),
+            ("uatom", "123")
+        );
+        assert!(results[1].clone().unwrap_err().contains("not allowed"));
+    }
+
+    #[test]
+    fn answers_polytone_notes() {
+        let mut deps = mock_dependencies_with_balances(&[("alice", &coins(123, "uatom"))]);
+        MAX_BATCH_SIZE.save(&mut deps.storage, &10).unwrap();
+
+        let handshake_open = mock_ibc_channel_open_try(CHANNEL, APP_ORDER, POLYTONE_VERSION);
+        let res = ibc_channel_open(deps.as_mut(), mock_env(), handshake_open)
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.version, POLYTONE_VERSION);
+        setup_channel(deps.as_mut(), CHANNEL, AppVersion::polytone());
+
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = Binary::from(
+            br#"{"sender":"bob","msg":{"query":{"msgs":[{"bank":{"balance":{"address":"alice","denom":"uatom"}}}]}}}"#,
+        );
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: PolytoneCallback = from_slice(&res.acknowledgement).unwrap();
+        let results = match ack {
+            PolytoneCallback::Query(Ok(results)) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        let balance: BalanceResponse = from_binary(&results[0]).unwrap();
+        assert_eq!(balance.amount, coin(123, "uatom"));
+
+        // notes can also execute, which we don't
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = Binary::from(br#"{"sender":"bob","msg":{"execute":{"msgs":[]}}}"#);
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let ack: PolytoneCallback = from_slice(&res.acknowledgement).unwrap();
+        assert!(matches!(ack, PolytoneCallback::FatalError(_)));
+    }
 }
@@ -0,0 +1,116 @@
+use cosmwasm_std::{
+    from_binary, to_binary, AllBalanceResponse, BalanceResponse, BankQuery, Binary, ContractResult,
+    Deps, QueryRequest, StdResult, SystemResult, WasmQuery,
+};
+use cw_ibc_query::{
+    decode_stargate_response, encode_stargate_response, ProtoCoin, QueryAllBalancesRequest,
+    QueryAllBalancesResponse, QueryBalanceRequest, QueryBalanceResponse,
+    QuerySmartContractStateRequest, QuerySmartContractStateResponse, ALL_BALANCES_PATH,
+    BANK_BALANCE_PATH, RAW_CONTRACT_STATE_PATH, SMART_CONTRACT_STATE_PATH,
+};
+
+use crate::custom_query::HostQuery;
+use crate::queryability::check_queryable;
+use crate::state::STARGATE_ALLOWLIST;
+
+/// Answers the ABCI queries of an async-icq controller. Like the native host, only paths on
+/// the allowlist are answered. Bank and wasm paths are served by the matching CosmWasm
+/// queries, so they work on chains that don't accept them as Stargate queries.
+pub fn run_abci_queries(
+    deps: Deps,
+    channel_id: &str,
+    msgs: Vec<QueryRequest<HostQuery>>,
+) -> StdResult<Vec<ContractResult<Binary>>> {
+    msgs.iter()
+        .map(|query| {
+            if let Some(err) = check_queryable(deps, channel_id, query)? {
+                return Ok(ContractResult::Err(err));
+            }
+            Ok(match query {
+                QueryRequest::Stargate { path, .. }
+                    if !STARGATE_ALLOWLIST.has(deps.storage, path) =>
+                {
+                    ContractResult::Err(format!("Stargate path {} is not allowed", path))
+                }
+                QueryRequest::Stargate { path, data } => answer(deps, path, data).into(),
+                // icq packets only decode to Stargate queries
+                _ => ContractResult::Err("Not an ABCI query".to_string()),
+            })
+        })
+        .collect()
+}
+
+fn answer(deps: Deps, path: &str, data: &[u8]) -> Result<Binary, String> {
+    let decode_err = |err: cosmwasm_std::StdError| err.to_string();
+    match path {
+        BANK_BALANCE_PATH => {
+            let req: QueryBalanceRequest = decode_stargate_response(data).map_err(decode_err)?;
+            let query = BankQuery::Balance {
+                address: req.address,
+                denom: req.denom,
+            };
+            let res: BalanceResponse =
+                from_binary(&raw_query(deps, query.into())?).map_err(decode_err)?;
+            Ok(encode_stargate_response(&QueryBalanceResponse {
+                balance: Some(ProtoCoin {
+                    denom: res.amount.denom,
+                    amount: res.amount.amount.to_string(),
+                }),
+            }))
+        }
+        ALL_BALANCES_PATH => {
+            let req: QueryAllBalancesRequest =
+                decode_stargate_response(data).map_err(decode_err)?;
+            let query = BankQuery::AllBalances {
+                address: req.address,
+            };
+            let res: AllBalanceResponse =
+                from_binary(&raw_query(deps, query.into())?).map_err(decode_err)?;
+            Ok(encode_stargate_response(&QueryAllBalancesResponse {
+                balances: res
+                    .amount
+                    .into_iter()
+                    .map(|coin| ProtoCoin {
+                        denom: coin.denom,
+                        amount: coin.amount.to_string(),
+                    })
+                    .collect(),
+            }))
+        }
+        SMART_CONTRACT_STATE_PATH | RAW_CONTRACT_STATE_PATH => {
+            let req: QuerySmartContractStateRequest =
+                decode_stargate_response(data).map_err(decode_err)?;
+            let query = if path == SMART_CONTRACT_STATE_PATH {
+                WasmQuery::Smart {
+                    contract_addr: req.address,
+                    msg: Binary(req.query_data),
+                }
+            } else {
+                WasmQuery::Raw {
+                    contract_addr: req.address,
+                    key: Binary(req.query_data),
+                }
+            };
+            let data = raw_query(deps, query.into())?;
+            Ok(encode_stargate_response(&QuerySmartContractStateResponse {
+                data: data.0,
+            }))
+        }
+        _ => raw_query(
+            deps,
+            QueryRequest::Stargate {
+                path: path.to_string(),
+                data: Binary(data.to_vec()),
+            },
+        ),
+    }
+}
+
+fn raw_query(deps: Deps, query: QueryRequest<HostQuery>) -> Result<Binary, String> {
+    let request = to_binary(&query).map_err(|err| err.to_string())?;
+    match deps.querier.raw_query(&request) {
+        SystemResult::Ok(ContractResult::Ok(data)) => Ok(data),
+        SystemResult::Ok(ContractResult::Err(err)) => Err(err),
+        SystemResult::Err(err) => Err(err.to_string()),
+    }
+}
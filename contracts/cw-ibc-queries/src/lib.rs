@@ -1,5 +1,19 @@
+pub mod batch;
+pub mod cache;
+pub mod coalesce;
 pub mod contract;
+pub mod custom_query;
 pub mod error;
+pub mod group;
 pub mod ibc;
+pub mod icq;
 pub mod msg;
+pub mod pagination;
+pub mod queryability;
+pub mod responder;
+pub mod shard;
 pub mod state;
+pub mod subscription;
+#[cfg(test)]
+mod testing;
+pub mod view;
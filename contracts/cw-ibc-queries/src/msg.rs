@@ -1,11 +1,22 @@
-use cosmwasm_std::{Empty, QueryRequest};
+use cosmwasm_std::{Addr, Binary, QueryRequest, Uint128};
+use cw_ibc_query::{Aggregation, AppVersion, PaginatedQuery, Projection, Quorum};
+use cw_utils::Expiration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::custom_query::HostQuery;
+use crate::state::{Queryability, Subscription, View};
+
 /// Just needs to know the code_id of a reflect contract to spawn sub-accounts
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub packet_lifetime: u64,
+    /// Manages the Stargate allowlist, defaults to the sender
+    pub admin: Option<String>,
+    /// Most queries we answer in one packet, defaults to 50
+    pub max_batch_size: Option<u32>,
+    /// Seconds successful results are cached for `max_age` queries, no caching without it
+    pub cache_ttl: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -14,8 +25,232 @@ pub enum ExecuteMsg {
     IbcQuery {
         channel_id: String,
         // Queries to be executed
-        msgs: Vec<QueryRequest<Empty>>,
+        msgs: Vec<QueryRequest<HostQuery>>,
         // Callback contract address that implements ReceiveIbcResponseMsg
         callback: String,
+        /// Answer from the cache, in this transaction, if every result is at most this many
+        /// seconds old. Can't be used with projections.
+        max_age: Option<u64>,
+        /// Have the host keep only parts of the results, one per query or none at all
+        #[serde(default)]
+        projections: Vec<Option<Projection>>,
+    },
+    /// Runs the queries in order on the host, each may use values of earlier results through
+    /// `${<index><pointer>}` placeholders, like `${0/voting_module}`
+    IbcQueryChain {
+        channel_id: String,
+        msgs: Vec<QueryRequest<HostQuery>>,
+        callback: String,
+        #[serde(default)]
+        projections: Vec<Option<Projection>>,
+    },
+    /// Has the host fold a number out of every result into one value, the callback gets a
+    /// single `AggregateResult`. Projections apply first, and the queries may be chained.
+    IbcQueryAggregate {
+        channel_id: String,
+        msgs: Vec<QueryRequest<HostQuery>>,
+        callback: String,
+        aggregation: Aggregation,
+        #[serde(default)]
+        projections: Vec<Option<Projection>>,
+        #[serde(default)]
+        chained: bool,
+    },
+    /// Runs the queries the host registered as view `name`, with its `${<name>}` placeholders
+    /// filled in from `params`
+    IbcQueryView {
+        channel_id: String,
+        name: String,
+        params: Vec<(String, String)>,
+        callback: String,
+    },
+    /// Forwards `request` to the contract the host registered as responder `name`, the
+    /// callback gets its answer as the only result
+    IbcQueryResponder {
+        channel_id: String,
+        name: String,
+        request: Binary,
+        callback: String,
+    },
+    /// Sends the same queries to every channel and reports all results in one
+    /// ReceiveIbcQueryGroupResponse callback, once each channel answered or timed out
+    IbcQueryMany {
+        channels: Vec<String>,
+        msgs: Vec<QueryRequest<HostQuery>>,
+        callback: String,
+        /// Seconds after which the results that arrived so far may be delivered
+        partial_after: Option<u64>,
+        /// Only accept results enough channels agree on, the callback reports the vote
+        quorum: Option<Quorum>,
+    },
+    /// Follows a paginated smart query until an empty page or `max_pages`, the callback
+    /// gets every item in one response shaped like a page
+    IbcQueryPaginated {
+        channel_id: String,
+        query: PaginatedQuery,
+        callback: String,
+    },
+    /// Delivers what a query group has after its deadline passed, anyone can call it
+    DeliverQueryGroup { group_id: u64 },
+    /// Registers queries to be sent every `interval`, funded by the single coin sent along
+    Subscribe(SubscribeMsg),
+    /// Adds the coin sent along to the subscription's deposit
+    FundSubscription { subscription_id: u64 },
+    /// Sends the subscription's queries once its interval passed, anyone can call it and
+    /// earns the bounty
+    Trigger { subscription_id: u64 },
+    /// Owner only
+    PauseSubscription { subscription_id: u64 },
+    /// Owner only
+    ResumeSubscription { subscription_id: u64 },
+    /// Owner only, refunds the rest of the deposit
+    CancelSubscription { subscription_id: u64 },
+    /// Sends everything queued on the channel in one packet, anyone can call it
+    Flush { channel_id: String },
+    /// Admin only, queues IbcQuery requests on the channel until `flush_at` queries wait.
+    /// `None` turns batching off.
+    UpdateBatching {
+        channel_id: String,
+        flush_at: Option<u32>,
+    },
+    /// Admin only, hands over control to a new address
+    UpdateAdmin { admin: String },
+    /// Admin only, sets how many seconds results are cached, 0 turns caching off
+    UpdateCacheTtl { ttl: u64 },
+    /// Admin only, registers queries remote chains can run by name, placeholders like
+    /// `${address}` are filled in from the request's params
+    SetView {
+        name: String,
+        queries: Vec<QueryRequest<HostQuery>>,
+        #[serde(default)]
+        chained: bool,
+    },
+    /// Admin only
+    RemoveView { name: String },
+    /// Registers a contract answering `ResponderQueryMsg` for requests to `name`. Our admin
+    /// may register any contract, a contract's admin only their own.
+    RegisterResponder { name: String, contract: String },
+    /// Our admin or the responder's admin
+    RemoveResponder { name: String },
+    /// Sets who may query `contract` from other chains, sent by the contract or its admin
+    SetQueryability {
+        contract: String,
+        queryability: Queryability,
+    },
+    /// Admin only, changes which gRPC paths remote chains may run as Stargate queries here
+    UpdateStargateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscribeMsg {
+    pub channel_id: String,
+    pub msgs: Vec<QueryRequest<HostQuery>>,
+    pub callback: String,
+    /// Least seconds between two triggers
+    pub interval: u64,
+    /// No triggers after this, defaults to never
+    pub expires: Option<Expiration>,
+    /// Paid from the deposit to whoever triggers the subscription
+    pub bounty: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    /// Returns AdminResponse
+    Admin {},
+    /// Returns StargateAllowlistResponse
+    StargateAllowlist {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Returns ViewsResponse
+    Views {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Returns RespondersResponse
+    Responders {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Returns QueryabilityResponse
+    Queryability { contract: String },
+    /// Returns ChannelResponse with the capabilities negotiated on the channel
+    Channel { channel_id: String },
+    /// Returns BatchQueueResponse
+    BatchQueue { channel_id: String },
+    /// Returns SubscriptionResponse
+    Subscription { subscription_id: u64 },
+    /// Returns SubscriptionsResponse
+    Subscriptions {
+        start_after: Option<u64>,
+        limit: Option<u32>,
     },
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AdminResponse {
+    pub admin: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StargateAllowlistResponse {
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelResponse {
+    pub channel_id: String,
+    pub negotiated: AppVersion,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionResponse {
+    pub subscription_id: u64,
+    pub subscription: Subscription,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SubscriptionsResponse {
+    pub subscriptions: Vec<SubscriptionResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchQueueResponse {
+    pub channel_id: String,
+    /// Queued queries that trigger a flush, none if batching is off
+    pub flush_at: Option<u32>,
+    pub queued: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ViewResponse {
+    pub name: String,
+    pub view: View,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ViewsResponse {
+    pub views: Vec<ViewResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ResponderResponse {
+    pub name: String,
+    pub contract: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RespondersResponse {
+    pub responders: Vec<ResponderResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueryabilityResponse {
+    pub contract: String,
+    pub queryability: Queryability,
+}
@@ -0,0 +1,333 @@
+use cosmwasm_std::{
+    ContractResult, DepsMut, Env, IbcBasicResponse, IbcMsg, Response, StdResult, Storage,
+};
+use cw_ibc_query::{IbcQueryResult, Json, PaginatedQuery, ProtocolVersion};
+
+use crate::contract::send_query;
+use crate::error::ContractError;
+use crate::ibc::{channel_version, send_query_result};
+use crate::state::{next_query_id, Pagination, PAGES, PAGINATIONS};
+
+/// Most bytes of items a paginated query collects. Every page rewrites all of them, so past
+/// this acks would get too expensive to relay and the query fails instead.
+pub const MAX_ITEMS_SIZE: u64 = 64 * 1024;
+
+/// Fetches every page of `query`, the callback gets them all at once
+pub fn execute_ibc_query_paginated(
+    mut deps: DepsMut,
+    env: Env,
+    channel_id: String,
+    mut query: PaginatedQuery,
+    callback: String,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&callback)?;
+    query.validate()?;
+    // v1 callbacks get the raw ack, which we can't read the cursor from
+    if channel_version(deps.as_ref(), &channel_id)?.version == ProtocolVersion::V1 {
+        return Err(ContractError::PaginationUnsupported(channel_id));
+    }
+
+    let query_id = next_query_id(deps.storage)?;
+    let pagination = Pagination {
+        channel_id,
+        query,
+        callback,
+        pages: 0,
+        items: vec![],
+        size: 0,
+    };
+    let msg = send_page(deps.branch(), &env, query_id, &pagination, None)?;
+    PAGINATIONS.save(deps.storage, query_id, &pagination)?;
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attribute("action", "ibc_query_paginated")
+        .add_attribute("query_id", query_id.to_string()))
+}
+
+/// Sends the query for the page after `cursor`, we get the answer
+fn send_page(
+    mut deps: DepsMut,
+    env: &Env,
+    query_id: u64,
+    pagination: &Pagination,
+    cursor: Option<Json>,
+) -> Result<IbcMsg, ContractError> {
+    let contract = env.contract.address.to_string();
+    let page = pagination.query.page(cursor)?;
+    let (page_id, msg) = send_query(
+        deps.branch(),
+        env,
+        contract.clone(),
+        pagination.channel_id.clone(),
+        vec![page],
+        contract,
+    )?;
+    PAGES.save(deps.storage, page_id, &query_id)?;
+    Ok(msg)
+}
+
+/// The paginated query a page belongs to, if it was one
+pub fn take_page(storage: &mut dyn Storage, page_id: u64) -> StdResult<Option<u64>> {
+    let query_id = PAGES.may_load(storage, page_id)?;
+    PAGES.remove(storage, page_id);
+    Ok(query_id)
+}
+
+/// Asks for the next page, or answers the callback once the last one arrived or the page cap
+/// is reached. A failed page fails the whole query, as do items over `MAX_ITEMS_SIZE`.
+pub fn follow_page(
+    mut deps: DepsMut,
+    env: &Env,
+    query_id: u64,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    let mut pagination = PAGINATIONS.load(deps.storage, query_id)?;
+    pagination.pages += 1;
+    let data = match &result {
+        IbcQueryResult::Ok(results) => match results.as_slice() {
+            [ContractResult::Ok(data)] => data,
+            _ => return finish(deps, query_id, pagination, result),
+        },
+        _ => return finish(deps, query_id, pagination, result),
+    };
+    let page = match pagination.query.read_page(data) {
+        Ok(page) => page,
+        Err(err) => {
+            let result = IbcQueryResult::Error(err.to_string());
+            return finish(deps, query_id, pagination, result);
+        }
+    };
+    pagination.size += page.items.iter().map(|item| item.len() as u64).sum::<u64>();
+    if pagination.size > MAX_ITEMS_SIZE {
+        let result = IbcQueryResult::Error(format!(
+            "Paginated query results exceed {} bytes",
+            MAX_ITEMS_SIZE
+        ));
+        return finish(deps, query_id, pagination, result);
+    }
+    pagination.items.extend(page.items);
+    if page.cursor.is_none() || pagination.pages >= pagination.query.max_pages {
+        let items = pagination.query.concat(&pagination.items)?;
+        let result = IbcQueryResult::Ok(vec![ContractResult::Ok(items)]);
+        return finish(deps, query_id, pagination, result);
+    }
+
+    let msg = send_page(deps.branch(), env, query_id, &pagination, page.cursor)?;
+    PAGINATIONS.save(deps.storage, query_id, &pagination)?;
+    Ok(IbcBasicResponse::new()
+        .add_message(msg)
+        .add_attribute("action", "acknowledge_ibc_query")
+        .add_attribute("query_id", query_id.to_string())
+        .add_attribute("pages", pagination.pages.to_string()))
+}
+
+fn finish(
+    deps: DepsMut,
+    query_id: u64,
+    pagination: Pagination,
+    result: IbcQueryResult,
+) -> Result<IbcBasicResponse, ContractError> {
+    PAGINATIONS.remove(deps.storage, query_id);
+    let res = send_query_result(pagination.callback, pagination.channel_id, query_id, result)?;
+    Ok(res.add_attribute("pages", pagination.pages.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_ibc_packet_ack, mock_info};
+    use cosmwasm_std::{
+        from_binary, Binary, CosmosMsg, Empty, IbcAcknowledgement, QueryRequest, SubMsg, WasmMsg,
+        WasmQuery,
+    };
+    use cw_ibc_query::{
+        PacketMsg, ReceiveIbcQueryResponseMsg, ReceiverExecuteMsg, WireFormat, MAX_PAGES,
+    };
+
+    use crate::contract::execute;
+    use crate::custom_query::HostQuery;
+    use crate::ibc::ibc_packet_ack;
+    use crate::msg::ExecuteMsg;
+    use crate::testing::{json_v2, setup, setup_channel};
+
+    use super::*;
+
+    const CHANNEL: &str = "channel-2";
+
+    fn format() -> WireFormat {
+        WireFormat::from(ProtocolVersion::V2)
+    }
+
+    /// The page query of the packet sent, if any
+    fn sent_page(messages: &[SubMsg]) -> Option<(Binary, Binary)> {
+        messages.iter().find_map(|msg| match &msg.msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => {
+                let packet = format()
+                    .decode_packet::<HostQuery>(data.as_slice())
+                    .unwrap();
+                match packet.msg {
+                    PacketMsg::IbcQuery { msgs, .. } => match &msgs[..] {
+                        [QueryRequest::Wasm(WasmQuery::Smart { msg, .. })] => {
+                            Some((data.clone(), msg.clone()))
+                        }
+                        _ => panic!("expected a smart query"),
+                    },
+                    _ => panic!("expected a query"),
+                }
+            }
+            _ => None,
+        })
+    }
+
+    fn ack(deps: DepsMut, data: Binary, page: &[u8]) -> IbcBasicResponse {
+        let ack = format()
+            .encode_ack(1, vec![ContractResult::Ok(page.into())])
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(CHANNEL, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = data;
+        ibc_packet_ack(deps, mock_env(), msg).unwrap()
+    }
+
+    /// Fetches cw20 accounts two at a time
+    fn all_accounts(channel_id: &str, max_pages: u32) -> ExecuteMsg {
+        ExecuteMsg::IbcQueryPaginated {
+            channel_id: channel_id.to_string(),
+            query: PaginatedQuery {
+                contract_addr: "cw20".to_string(),
+                msg: br#"{"all_accounts":{"limit":2}}"#.into(),
+                cursor_field: "start_after".to_string(),
+                items_field: "accounts".to_string(),
+                key_field: None,
+                max_pages,
+            },
+            callback: "callback".to_string(),
+        }
+    }
+
+    /// Starts fetching cw20 accounts, returns the first page's packet and query
+    fn query_accounts(mut deps: DepsMut, max_pages: u32) -> (Binary, Binary) {
+        setup(deps.branch(), "admin");
+        setup_channel(deps.branch(), CHANNEL, json_v2(10));
+        let msg = all_accounts(CHANNEL, max_pages);
+        let res = execute(deps, mock_env(), mock_info("requester", &[]), msg).unwrap();
+        sent_page(&res.messages).unwrap()
+    }
+
+    #[test]
+    fn channels_without_a_version_cannot_paginate() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), "admin");
+
+        // opened before the upgrade, so it speaks v1
+        let msg = all_accounts("channel-5", 10);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("requester", &[]), msg);
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::PaginationUnsupported("channel-5".to_string())
+        );
+    }
+
+    #[test]
+    fn asks_for_the_page_after_the_last_key() {
+        let mut deps = mock_dependencies();
+        let (data, query) = query_accounts(deps.as_mut(), 10);
+        assert_eq!(query, Binary::from(br#"{"all_accounts":{"limit":2}}"#));
+
+        let res = ack(deps.as_mut(), data, br#"{"accounts":["alice","bob"]}"#);
+        let (_, query) = sent_page(&res.messages).unwrap();
+        assert_eq!(
+            query,
+            Binary::from(br#"{"all_accounts":{"limit":2,"start_after":"bob"}}"#)
+        );
+    }
+
+    #[test]
+    fn follows_pages_until_an_empty_one() {
+        let mut deps = mock_dependencies();
+        let (data, _) = query_accounts(deps.as_mut(), 10);
+        let res = ack(deps.as_mut(), data, br#"{"accounts":["alice","bob"]}"#);
+        let (data, _) = sent_page(&res.messages).unwrap();
+        let res = ack(deps.as_mut(), data, br#"{"accounts":["carol"]}"#);
+        let (data, _) = sent_page(&res.messages).unwrap();
+
+        let res = ack(deps.as_mut(), data, br#"{"accounts":[]}"#);
+        assert_eq!(sent_page(&res.messages), None);
+        let callback: ReceiverExecuteMsg = match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).unwrap(),
+            _ => panic!("expected a callback"),
+        };
+        let items = br#"{"accounts":["alice","bob","carol"]}"#;
+        assert_eq!(
+            callback,
+            ReceiverExecuteMsg::ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg {
+                channel_id: CHANNEL.to_string(),
+                query_id: 1,
+                result: IbcQueryResult::Ok(vec![ContractResult::Ok(items.into())]),
+            })
+        );
+        assert!(!PAGINATIONS.has(&deps.storage, 1));
+    }
+
+    fn callback_result(res: &IbcBasicResponse) -> IbcQueryResult {
+        match &res.messages[..] {
+            [msg] => match &msg.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => match from_binary(msg).unwrap() {
+                    ReceiverExecuteMsg::ReceiveIbcQueryResponse(msg) => msg.result,
+                    other => panic!("unexpected callback {:?}", other),
+                },
+                _ => panic!("expected a callback"),
+            },
+            _ => panic!("expected one callback"),
+        }
+    }
+
+    #[test]
+    fn stops_at_max_pages() {
+        let mut deps = mock_dependencies();
+        // asks for more than anyone may
+        let (mut data, _) = query_accounts(deps.as_mut(), 1000);
+        for page in 1..MAX_PAGES {
+            let res = ack(
+                deps.as_mut(),
+                data,
+                format!(r#"{{"accounts":["{}"]}}"#, page).as_bytes(),
+            );
+            data = sent_page(&res.messages).unwrap().0;
+        }
+        let res = ack(deps.as_mut(), data, br#"{"accounts":["last"]}"#);
+        assert_eq!(sent_page(&res.messages), None);
+        let items: Vec<_> = (1..MAX_PAGES)
+            .map(|page| format!(r#""{}""#, page))
+            .collect();
+        let items = format!(r#"{{"accounts":[{},"last"]}}"#, items.join(","));
+        assert_eq!(
+            callback_result(&res),
+            IbcQueryResult::Ok(vec![ContractResult::Ok(items.into_bytes().into())])
+        );
+    }
+
+    #[test]
+    fn fails_past_the_size_limit() {
+        let mut deps = mock_dependencies();
+        let (data, _) = query_accounts(deps.as_mut(), 10);
+        let res = ack(deps.as_mut(), data, br#"{"accounts":["alice"]}"#);
+        let (data, _) = sent_page(&res.messages).unwrap();
+
+        let huge = "a".repeat(MAX_ITEMS_SIZE as usize);
+        let res = ack(
+            deps.as_mut(),
+            data,
+            format!(r#"{{"accounts":["{}"]}}"#, huge).as_bytes(),
+        );
+        assert_eq!(sent_page(&res.messages), None);
+        assert_eq!(
+            callback_result(&res),
+            IbcQueryResult::Error(format!(
+                "Paginated query results exceed {} bytes",
+                MAX_ITEMS_SIZE
+            ))
+        );
+        assert!(!PAGINATIONS.has(&deps.storage, 1));
+    }
+}
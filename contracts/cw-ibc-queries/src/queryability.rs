@@ -0,0 +1,293 @@
+use cosmwasm_std::{
+    ChannelResponse, ContractInfoResponse, Deps, DepsMut, IbcQuery, MessageInfo, QueryRequest,
+    Response, StdResult, WasmQuery,
+};
+use cw_ibc_query::{
+    decode_stargate_response, QuerySmartContractStateRequest, RAW_CONTRACT_STATE_PATH,
+    SMART_CONTRACT_STATE_PATH,
+};
+
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::msg::QueryabilityResponse;
+use crate::state::{Queryability, CONNECTIONS, QUERYABILITY};
+
+/// Sets who may query `contract` from other chains. Only the contract itself or its admin may
+/// do so.
+pub fn execute_set_queryability(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+    queryability: Queryability,
+) -> Result<Response, ContractError> {
+    let contract = deps.api.addr_validate(&contract)?;
+    if info.sender != contract {
+        let query = WasmQuery::ContractInfo {
+            contract_addr: contract.to_string(),
+        };
+        let contract_info: ContractInfoResponse = deps.querier.query(&query.into())?;
+        if contract_info.admin.as_deref() != Some(info.sender.as_str()) {
+            return Err(ContractError::Unauthorized);
+        }
+    }
+    match queryability {
+        Queryability::Public => QUERYABILITY.remove(deps.storage, &contract),
+        _ => QUERYABILITY.save(deps.storage, &contract, &queryability)?,
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_queryability")
+        .add_attribute("contract", contract))
+}
+
+/// Why a query may not run for a packet that arrived on `channel_id`, if it may not. Covers
+/// smart and raw queries, including their Stargate twins.
+pub fn check_queryable(
+    deps: Deps,
+    channel_id: &str,
+    query: &QueryRequest<HostQuery>,
+) -> StdResult<Option<String>> {
+    let contract = match query {
+        QueryRequest::Wasm(WasmQuery::Smart { contract_addr, .. })
+        | QueryRequest::Wasm(WasmQuery::Raw { contract_addr, .. }) => contract_addr.clone(),
+        QueryRequest::Stargate { path, data }
+            if path == SMART_CONTRACT_STATE_PATH || path == RAW_CONTRACT_STATE_PATH =>
+        {
+            // both requests start with the address, malformed ones fail when answered
+            match decode_stargate_response::<QuerySmartContractStateRequest>(data) {
+                Ok(req) => req.address,
+                Err(_) => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+    // the chain answers any spelling of an address, so look up its normal form. What can't
+    // be normalised isn't answered.
+    let addr = deps
+        .api
+        .addr_canonicalize(&contract)
+        .and_then(|canonical| deps.api.addr_humanize(&canonical));
+    let allowed = match addr {
+        Ok(addr) => match QUERYABILITY.may_load(deps.storage, &addr)? {
+            None | Some(Queryability::Public) => true,
+            Some(Queryability::Private) => false,
+            Some(Queryability::Restricted {
+                channels,
+                connections,
+            }) => {
+                channels.iter().any(|c| c == channel_id)
+                    || connection_of(deps, channel_id)?
+                        .is_some_and(|connection| connections.contains(&connection))
+            }
+        },
+        Err(_) => false,
+    };
+    Ok((!allowed).then(|| {
+        format!(
+            "Contract {} can't be queried from channel {}",
+            contract, channel_id
+        )
+    }))
+}
+
+/// The connection of one of our channels, if it has one. Channels opened before we recorded
+/// connections are looked up on the chain.
+fn connection_of(deps: Deps, channel_id: &str) -> StdResult<Option<String>> {
+    if let Some(connection) = CONNECTIONS.may_load(deps.storage, channel_id)? {
+        return Ok(Some(connection));
+    }
+    let query = IbcQuery::Channel {
+        channel_id: channel_id.to_string(),
+        port_id: None,
+    };
+    let res: Option<ChannelResponse> = deps.querier.query(&query.into()).ok();
+    Ok(res
+        .and_then(|res| res.channel)
+        .map(|channel| channel.connection_id))
+}
+
+pub fn query_queryability(deps: Deps, contract: String) -> StdResult<QueryabilityResponse> {
+    let addr = deps.api.addr_validate(&contract)?;
+    Ok(QueryabilityResponse {
+        queryability: QUERYABILITY
+            .may_load(deps.storage, &addr)?
+            .unwrap_or(Queryability::Public),
+        contract,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_ibc_channel, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        from_slice, to_binary, Addr, Binary, ContractResult, Empty, IbcOrder, OwnedDeps, Querier,
+        QuerierResult, SystemError, SystemResult,
+    };
+
+    use crate::ibc::run_queries;
+
+    use super::*;
+
+    /// A host where `vault_team` administers `vault`, and channel-2 is on connection-7
+    fn vault_host() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::ContractInfo { contract_addr } if contract_addr == "vault" => {
+                let mut info = ContractInfoResponse::new(1, "creator");
+                info.admin = Some("vault_team".to_string());
+                SystemResult::Ok(to_binary(&info).into())
+            }
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == "vault" => {
+                SystemResult::Ok(ContractResult::Ok(Binary::from(b"{}")))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+        CONNECTIONS
+            .save(&mut deps.storage, "channel-2", &"connection-7".to_string())
+            .unwrap();
+        deps
+    }
+
+    /// Knows the channel ends of channel-5, which is on connection-2
+    struct ChannelQuerier(MockQuerier);
+
+    impl Querier for ChannelQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            match from_slice(bin_request) {
+                Ok(QueryRequest::<Empty>::Ibc(IbcQuery::Channel { channel_id, .. })) => {
+                    let channel = (channel_id == "channel-5")
+                        .then(|| mock_ibc_channel(&channel_id, IbcOrder::Unordered, "v1"));
+                    SystemResult::Ok(to_binary(&ChannelResponse { channel }).into())
+                }
+                _ => self.0.raw_query(bin_request),
+            }
+        }
+    }
+
+    fn set(deps: DepsMut, sender: &str, queryability: Queryability) {
+        let info = mock_info(sender, &[]);
+        execute_set_queryability(deps, info, "vault".to_string(), queryability).unwrap();
+    }
+
+    /// Smart queries the vault for a packet from `channel_id`
+    fn run(deps: Deps, channel_id: &str) -> ContractResult<Binary> {
+        let query = QueryRequest::<HostQuery>::Wasm(WasmQuery::Smart {
+            contract_addr: "vault".to_string(),
+            msg: to_binary(&Empty {}).unwrap(),
+        });
+        run_queries(deps, channel_id, vec![query])
+            .unwrap()
+            .remove(0)
+    }
+
+    fn answered() -> ContractResult<Binary> {
+        ContractResult::Ok(b"{}".into())
+    }
+
+    fn denied(channel_id: &str) -> ContractResult<Binary> {
+        ContractResult::Err(format!(
+            "Contract vault can't be queried from channel {}",
+            channel_id
+        ))
+    }
+
+    #[test]
+    fn only_the_contract_or_its_admin_decide() {
+        let mut deps = vault_host();
+        let err = execute_set_queryability(
+            deps.as_mut(),
+            mock_info("stranger", &[]),
+            "vault".to_string(),
+            Queryability::Private,
+        );
+        assert_eq!(err.unwrap_err(), ContractError::Unauthorized);
+        set(deps.as_mut(), "vault_team", Queryability::Private);
+        set(deps.as_mut(), "vault", Queryability::Private);
+        assert_eq!(
+            query_queryability(deps.as_ref(), "vault".to_string())
+                .unwrap()
+                .queryability,
+            Queryability::Private
+        );
+    }
+
+    #[test]
+    fn restricted_contracts_answer_listed_channels_and_chains() {
+        let mut deps = vault_host();
+        let restricted = Queryability::Restricted {
+            channels: vec!["channel-1".to_string()],
+            connections: vec!["connection-7".to_string()],
+        };
+        set(deps.as_mut(), "vault_team", restricted);
+
+        assert_eq!(run(deps.as_ref(), "channel-1"), answered());
+        // channel-2 is on a listed connection
+        assert_eq!(run(deps.as_ref(), "channel-2"), answered());
+        assert_eq!(run(deps.as_ref(), "channel-3"), denied("channel-3"));
+    }
+
+    #[test]
+    fn connections_of_older_channels_are_looked_up() {
+        let deps = vault_host();
+        let mut deps = OwnedDeps {
+            storage: deps.storage,
+            api: deps.api,
+            querier: ChannelQuerier(deps.querier),
+            custom_query_type: PhantomData,
+        };
+        let restricted = Queryability::Restricted {
+            channels: vec![],
+            connections: vec!["connection-2".to_string()],
+        };
+        let info = mock_info("vault_team", &[]);
+        execute_set_queryability(deps.as_mut(), info, "vault".to_string(), restricted).unwrap();
+
+        // neither channel was recorded when it connected
+        assert_eq!(run(deps.as_ref(), "channel-5"), answered());
+        assert_eq!(run(deps.as_ref(), "channel-6"), denied("channel-6"));
+    }
+
+    #[test]
+    fn other_spellings_of_an_address_are_checked() {
+        let mut deps = vault_host();
+        set(deps.as_mut(), "vault", Queryability::Private);
+
+        for contract_addr in ["VAULT", "va"] {
+            let query = QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: contract_addr.to_string(),
+                msg: to_binary(&Empty {}).unwrap(),
+            });
+            let err = check_queryable(deps.as_ref(), "channel-1", &query).unwrap();
+            assert_eq!(
+                err,
+                Some(format!(
+                    "Contract {} can't be queried from channel channel-1",
+                    contract_addr
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn private_contracts_answer_nobody() {
+        let mut deps = vault_host();
+        assert_eq!(run(deps.as_ref(), "channel-1"), answered());
+        set(deps.as_mut(), "vault", Queryability::Private);
+        assert_eq!(run(deps.as_ref(), "channel-1"), denied("channel-1"));
+    }
+
+    #[test]
+    fn going_public_forgets_the_entry() {
+        let mut deps = vault_host();
+        set(deps.as_mut(), "vault", Queryability::Private);
+        set(deps.as_mut(), "vault", Queryability::Public);
+        assert!(!QUERYABILITY.has(&deps.storage, &Addr::unchecked("vault")));
+        assert_eq!(run(deps.as_ref(), "channel-3"), answered());
+    }
+}
@@ -0,0 +1,256 @@
+use cosmwasm_std::{
+    to_binary, Addr, Binary, ContractInfoResponse, ContractResult, Deps, DepsMut, MessageInfo,
+    Order, QueryRequest, Response, StdResult, SystemResult, WasmQuery,
+};
+use cw_ibc_query::{IbcRequestMsg, ResponderQueryMsg};
+use cw_storage_plus::Bound;
+
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::msg::{ResponderResponse, RespondersResponse};
+use crate::queryability::check_queryable;
+use crate::state::{ADMIN, RESPONDERS};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Registers `contract` to answer requests for `name`. Our admin may register any contract,
+/// a contract's admin only their own, and neither may take over another contract's name.
+pub fn execute_register_responder(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    contract: String,
+) -> Result<Response, ContractError> {
+    let contract = deps.api.addr_validate(&contract)?;
+    if let Some(registered) = RESPONDERS.may_load(deps.storage, &name)? {
+        assert_manages(deps.as_ref(), &info, &registered)?;
+    }
+    assert_manages(deps.as_ref(), &info, &contract)?;
+    RESPONDERS.save(deps.storage, &name, &contract)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "register_responder")
+        .add_attribute("name", name)
+        .add_attribute("contract", contract))
+}
+
+/// Our admin or the responder's admin
+pub fn execute_remove_responder(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    let registered = RESPONDERS
+        .may_load(deps.storage, &name)?
+        .ok_or_else(|| ContractError::UnknownResponder(name.clone()))?;
+    assert_manages(deps.as_ref(), &info, &registered)?;
+    RESPONDERS.remove(deps.storage, &name);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_responder")
+        .add_attribute("name", name))
+}
+
+/// Whether the sender is our admin or the admin of `contract`
+fn assert_manages(deps: Deps, info: &MessageInfo, contract: &Addr) -> Result<(), ContractError> {
+    if ADMIN.load(deps.storage)? == info.sender {
+        return Ok(());
+    }
+    let query = WasmQuery::ContractInfo {
+        contract_addr: contract.to_string(),
+    };
+    let contract_info: ContractInfoResponse = deps.querier.query(&query.into())?;
+    if contract_info.admin.as_deref() != Some(info.sender.as_str()) {
+        return Err(ContractError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Forwards a request to the responder registered as `name`, its answer is the result.
+/// Responders must be queryable from the request's channel like any other contract.
+pub fn ask_responder(
+    deps: Deps,
+    name: &str,
+    request: IbcRequestMsg,
+) -> Result<ContractResult<Binary>, ContractError> {
+    let contract = RESPONDERS
+        .may_load(deps.storage, name)?
+        .ok_or_else(|| ContractError::UnknownResponder(name.to_string()))?;
+    let channel_id = request.channel_id.clone();
+    let query: QueryRequest<HostQuery> = QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: contract.into(),
+        msg: to_binary(&ResponderQueryMsg::IbcRequest(request))?,
+    });
+    if let Some(err) = check_queryable(deps, &channel_id, &query)? {
+        return Ok(ContractResult::Err(err));
+    }
+    Ok(match deps.querier.raw_query(&to_binary(&query)?) {
+        SystemResult::Ok(res) => res,
+        SystemResult::Err(err) => ContractResult::Err(err.to_string()),
+    })
+}
+
+pub fn query_responders(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<RespondersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let responders = RESPONDERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(name, contract)| ResponderResponse { name, contract }))
+        .collect::<StdResult<_>>()?;
+    Ok(RespondersResponse { responders })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_packet_recv, mock_info, MockApi, MockQuerier,
+        MockStorage,
+    };
+    use cosmwasm_std::{from_slice, Empty, OwnedDeps, SystemError};
+    use cw_ibc_query::{IbcQueryResult, PacketMsg, PacketMsgV2, ProtocolVersion, WireFormat};
+
+    use crate::contract::execute;
+    use crate::ibc::ibc_packet_receive;
+    use crate::msg::ExecuteMsg;
+    use crate::state::Queryability;
+    use crate::testing::{json_v2, setup, setup_channel};
+
+    use super::*;
+
+    const CHANNEL: &str = "channel-8";
+
+    /// A host where `risk_team` administers `risk_engine`, which echoes who asked what
+    fn risk_host() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::ContractInfo { contract_addr } if contract_addr == "risk_engine" => {
+                let mut info = ContractInfoResponse::new(1, "creator");
+                info.admin = Some("risk_team".to_string());
+                SystemResult::Ok(to_binary(&info).into())
+            }
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "risk_engine" => {
+                let ResponderQueryMsg::IbcRequest(request) = from_slice(msg).unwrap();
+                let body = String::from_utf8(request.request.to_vec()).unwrap();
+                let answer = format!(r#"{{"sender":"{}","request":{}}}"#, request.sender, body);
+                SystemResult::Ok(ContractResult::Ok(answer.into_bytes().into()))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+        setup(deps.as_mut(), "admin");
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+        deps
+    }
+
+    fn register() -> ExecuteMsg {
+        ExecuteMsg::RegisterResponder {
+            name: "risk".to_string(),
+            contract: "risk_engine".to_string(),
+        }
+    }
+
+    fn ask(deps: DepsMut) -> IbcQueryResult {
+        let packet: PacketMsgV2 = PacketMsgV2 {
+            id: 6,
+            sender: "alice".to_string(),
+            msg: PacketMsg::Responder {
+                name: "risk".to_string(),
+                request: Binary::from(br#"{"score":{"address":"bob"}}"#),
+                callback: "callback".to_string(),
+            },
+        };
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = to_binary(&packet).unwrap();
+        let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+        WireFormat::from(ProtocolVersion::V2)
+            .decode_ack(&res.acknowledgement)
+            .unwrap()
+    }
+
+    #[test]
+    fn only_admins_register_responders() {
+        let mut deps = risk_host();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("stranger", &[]),
+            register(),
+        );
+        assert_eq!(err.unwrap_err(), ContractError::Unauthorized);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("risk_team", &[]),
+            register(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn responders_answer_requests() {
+        let mut deps = risk_host();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("risk_team", &[]),
+            register(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            ask(deps.as_mut()),
+            IbcQueryResult::Ok(vec![ContractResult::Ok(Binary::from(
+                br#"{"sender":"alice","request":{"score":{"address":"bob"}}}"#
+            ))])
+        );
+    }
+
+    #[test]
+    fn removed_responders_are_unknown() {
+        let mut deps = risk_host();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("risk_team", &[]),
+            register(),
+        )
+        .unwrap();
+
+        let remove = ExecuteMsg::RemoveResponder {
+            name: "risk".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), remove).unwrap();
+        assert_eq!(
+            ask(deps.as_mut()),
+            IbcQueryResult::Error("simple-ica-v2: Unknown responder risk".to_string())
+        );
+    }
+
+    #[test]
+    fn private_responders_are_refused() {
+        let mut deps = risk_host();
+        let risk_team = mock_info("risk_team", &[]);
+        execute(deps.as_mut(), mock_env(), risk_team.clone(), register()).unwrap();
+
+        let msg = ExecuteMsg::SetQueryability {
+            contract: "risk_engine".to_string(),
+            queryability: Queryability::Private,
+        };
+        execute(deps.as_mut(), mock_env(), risk_team, msg).unwrap();
+        assert_eq!(
+            ask(deps.as_mut()),
+            IbcQueryResult::Ok(vec![ContractResult::Err(format!(
+                "Contract risk_engine can't be queried from channel {}",
+                CHANNEL
+            ))])
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use cosmwasm_std::{Deps, DepsMut, Env, Order, QueryRequest, Response, StdResult, Storage};
+use cw_ibc_query::{IbcQueryResult, PacketMsg, PacketMsgV2, ProtocolVersion, MAX_PACKET_SIZE};
+
+use crate::contract::send_query;
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::state::{next_query_id, ShardedQuery, CHANNELS, SHARDED_QUERIES, SHARDS, SHARD_RESULTS};
+
+/// Splits `msgs` into shards that each fit in one packet on the channel. None if they fit in
+/// one already, or the channel can't have them reassembled.
+pub fn plan_shards(
+    deps: Deps,
+    env: &Env,
+    channel_id: &str,
+    sender: &str,
+    callback: &str,
+    msgs: &[QueryRequest<HostQuery>],
+) -> Result<Option<Vec<Vec<QueryRequest<HostQuery>>>>, ContractError> {
+    // v1 callbacks get the raw ack, which can't be reassembled
+    let channel = match CHANNELS.may_load(deps.storage, channel_id)? {
+        Some(channel) if channel.negotiated.version != ProtocolVersion::V1 => channel,
+        _ => return Ok(None),
+    };
+    // not every format can encode every query
+    msgs.iter()
+        .try_for_each(|query| channel.negotiated.supports(query))?;
+    let format = channel.negotiated.wire_format();
+    let max_batch_size = channel.negotiated.max_batch_size as usize;
+    let packet_size = |sender: &str, callback: &str, msgs: &[QueryRequest<HostQuery>]| {
+        let packet = PacketMsgV2 {
+            id: u64::MAX,
+            sender: sender.to_string(),
+            msg: PacketMsg::IbcQuery {
+                msgs: msgs.to_vec(),
+                callback: callback.to_string(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        };
+        format.encode_packet(&packet).map(|data| data.len())
+    };
+    if msgs.len() <= max_batch_size && packet_size(sender, callback, msgs)? <= MAX_PACKET_SIZE {
+        return Ok(None);
+    }
+
+    // shards are sent by us, and answered to us. Every query is measured once, a shard's
+    // packet is the empty one plus its queries and a separator after each.
+    let contract = env.contract.address.as_str();
+    let too_large = |size| ContractError::PacketTooLarge {
+        size,
+        max: MAX_PACKET_SIZE,
+    };
+    let envelope = packet_size(contract, contract, &[])?;
+    let mut shards = vec![];
+    let mut shard = vec![];
+    let mut shard_size = envelope;
+    for query in msgs {
+        let alone = packet_size(contract, contract, std::slice::from_ref(query))?;
+        if alone > MAX_PACKET_SIZE {
+            return Err(too_large(alone));
+        }
+        let size = alone.saturating_sub(envelope) + 1;
+        if !shard.is_empty()
+            && (shard.len() == max_batch_size || shard_size + size > MAX_PACKET_SIZE)
+        {
+            shards.push(std::mem::take(&mut shard));
+            shard_size = envelope;
+        }
+        shard.push(query.clone());
+        shard_size += size;
+    }
+    shards.push(shard);
+    // compression makes the sum an estimate, what goes out must fit
+    for shard in &shards {
+        let size = packet_size(contract, contract, shard)?;
+        if size > MAX_PACKET_SIZE {
+            return Err(too_large(size));
+        }
+    }
+    Ok(Some(shards))
+}
+
+/// Sends every shard under one query id, the callback gets the reassembled results
+pub fn send_shards(
+    mut deps: DepsMut,
+    env: &Env,
+    channel_id: String,
+    shards: Vec<Vec<QueryRequest<HostQuery>>>,
+    callback: String,
+) -> Result<Response, ContractError> {
+    let query_id = next_query_id(deps.storage)?;
+    let contract = env.contract.address.to_string();
+    let mut res = Response::new();
+    let mut shard_ids = vec![];
+    let mut queries = vec![];
+    for msgs in shards {
+        queries.push(msgs.len() as u32);
+        let (shard_id, msg) = send_query(
+            deps.branch(),
+            env,
+            contract.clone(),
+            channel_id.clone(),
+            msgs,
+            contract.clone(),
+        )?;
+        SHARDS.save(deps.storage, shard_id, &query_id)?;
+        shard_ids.push(shard_id);
+        res = res.add_message(msg);
+    }
+    let shards = shard_ids.len();
+    let query = ShardedQuery {
+        callback,
+        shards: shard_ids,
+        queries,
+    };
+    SHARDED_QUERIES.save(deps.storage, query_id, &query)?;
+
+    Ok(res
+        .add_attribute("action", "handle_check_remote_balance")
+        .add_attribute("query_id", query_id.to_string())
+        .add_attribute("shards", shards.to_string()))
+}
+
+/// The query a shard belongs to, if it was one
+pub fn take_shard(storage: &mut dyn Storage, shard_id: u64) -> StdResult<Option<u64>> {
+    let query_id = SHARDS.may_load(storage, shard_id)?;
+    SHARDS.remove(storage, shard_id);
+    Ok(query_id)
+}
+
+/// Keeps a shard's result. Returns the callback and the whole result once every shard
+/// succeeded, or as soon as one failed or answered a different number of queries than it had.
+pub fn record_shard_result(
+    storage: &mut dyn Storage,
+    query_id: u64,
+    shard_id: u64,
+    result: IbcQueryResult,
+) -> StdResult<Option<(String, IbcQueryResult)>> {
+    // already answered with another shard's failure
+    let query = match SHARDED_QUERIES.may_load(storage, query_id)? {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+    let expected = query
+        .shards
+        .iter()
+        .position(|id| *id == shard_id)
+        .map(|i| query.queries[i] as usize);
+    let whole = match result {
+        IbcQueryResult::Ok(results) if Some(results.len()) != expected => {
+            IbcQueryResult::Error(format!(
+                "Shard {} answered {} results for {} queries",
+                shard_id,
+                results.len(),
+                expected.unwrap_or_default()
+            ))
+        }
+        IbcQueryResult::Ok(_) => {
+            SHARD_RESULTS.save(storage, (query_id, shard_id), &result)?;
+            if !query
+                .shards
+                .iter()
+                .all(|id| SHARD_RESULTS.has(storage, (query_id, *id)))
+            {
+                return Ok(None);
+            }
+            let mut results = vec![];
+            for id in &query.shards {
+                if let IbcQueryResult::Ok(shard) = SHARD_RESULTS.load(storage, (query_id, *id))? {
+                    results.extend(shard);
+                }
+            }
+            IbcQueryResult::Ok(results)
+        }
+        failed => failed,
+    };
+
+    let arrived: Vec<u64> = SHARD_RESULTS
+        .prefix(query_id)
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for id in arrived {
+        SHARD_RESULTS.remove(storage, (query_id, id));
+    }
+    SHARDED_QUERIES.remove(storage, query_id);
+    Ok(Some((query.callback, whole)))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_packet_ack, mock_ibc_packet_timeout, mock_info,
+    };
+    use cosmwasm_std::{
+        from_binary, BankQuery, Binary, ContractResult, CosmosMsg, Empty, IbcAcknowledgement,
+        IbcBasicResponse, WasmMsg,
+    };
+    use cw_ibc_query::{AppVersion, ReceiveIbcQueryResponseMsg, ReceiverExecuteMsg, WireFormat};
+
+    use crate::contract::execute;
+    use crate::ibc::{ibc_packet_ack, ibc_packet_timeout};
+    use crate::msg::ExecuteMsg;
+    use crate::testing::{balance_query, json_v2, packets, setup, setup_channel};
+
+    use super::*;
+
+    const CHANNEL: &str = "channel-4";
+
+    fn result(data: &str) -> ContractResult<Binary> {
+        ContractResult::Ok(Binary::from(data.as_bytes()))
+    }
+
+    fn ack(deps: DepsMut, data: &Binary, results: &[&str]) -> IbcBasicResponse {
+        let results = results.iter().map(|data| result(data)).collect();
+        let ack = WireFormat::from(ProtocolVersion::V2)
+            .encode_ack(1, results)
+            .unwrap();
+        let mut msg =
+            mock_ibc_packet_ack(CHANNEL, &Empty {}, IbcAcknowledgement::new(ack)).unwrap();
+        msg.original_packet.data = data.clone();
+        ibc_packet_ack(deps, mock_env(), msg).unwrap()
+    }
+
+    fn callback(res: &IbcBasicResponse) -> ReceiveIbcQueryResponseMsg {
+        match &res.messages[..] {
+            [msg] => match &msg.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => match from_binary(msg).unwrap() {
+                    ReceiverExecuteMsg::ReceiveIbcQueryResponse(msg) => msg,
+                    other => panic!("unexpected callback {:?}", other),
+                },
+                _ => panic!("expected a callback"),
+            },
+            _ => panic!("expected one callback"),
+        }
+    }
+
+    /// Sends `count` queries on a channel taking 2 per packet
+    fn send_sharded(mut deps: DepsMut, count: usize) -> Vec<Binary> {
+        setup(deps.branch(), "admin");
+        setup_channel(deps.branch(), CHANNEL, json_v2(2));
+        let addresses: Vec<_> = (0..count).map(|i| format!("account-{}", i)).collect();
+        let msg = balance_query(CHANNEL, &addresses, "callback", None);
+        let res = execute(deps, mock_env(), mock_info("requester", &[]), msg).unwrap();
+        packets(&res)
+            .into_iter()
+            .map(|packet| packet.data)
+            .collect()
+    }
+
+    #[test]
+    fn shards_are_reassembled_in_order() {
+        let mut deps = mock_dependencies();
+        let sent = send_sharded(deps.as_mut(), 5);
+        assert_eq!(sent.len(), 3);
+
+        // shards may come back in any order, the callback waits for all of them
+        let res = ack(deps.as_mut(), &sent[2], &["5"]);
+        assert!(res.messages.is_empty());
+        let res = ack(deps.as_mut(), &sent[0], &["1", "2"]);
+        assert!(res.messages.is_empty());
+        let res = ack(deps.as_mut(), &sent[1], &["3", "4"]);
+        assert_eq!(
+            callback(&res),
+            ReceiveIbcQueryResponseMsg {
+                channel_id: CHANNEL.to_string(),
+                query_id: 1,
+                result: IbcQueryResult::Ok(
+                    ["1", "2", "3", "4", "5"].into_iter().map(result).collect()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn one_failed_shard_fails_the_query() {
+        let mut deps = mock_dependencies();
+        let sent = send_sharded(deps.as_mut(), 3);
+
+        let mut msg = mock_ibc_packet_timeout(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = sent[1].clone();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(callback(&res).result, IbcQueryResult::Timeout {});
+        // the rest is dropped
+        let res = ack(deps.as_mut(), &sent[0], &["1", "2"]);
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn miscounted_shards_fail_the_query() {
+        let mut deps = mock_dependencies();
+        let sent = send_sharded(deps.as_mut(), 5);
+
+        // shard 2 had two queries, one result would shift all later ones
+        let res = ack(deps.as_mut(), &sent[0], &["1"]);
+        assert_eq!(
+            callback(&res).result,
+            IbcQueryResult::Error("Shard 2 answered 1 results for 2 queries".to_string())
+        );
+        let res = ack(deps.as_mut(), &sent[1], &["3", "4"]);
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn large_queries_are_split_by_size() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), "admin");
+        let uncompressed = AppVersion {
+            compression: vec![],
+            ..json_v2(10)
+        };
+        setup_channel(deps.as_mut(), CHANNEL, uncompressed);
+
+        // two fit in a packet, three don't
+        let address = "a".repeat(MAX_PACKET_SIZE * 2 / 5);
+        let msg = ExecuteMsg::IbcQuery {
+            channel_id: CHANNEL.to_string(),
+            msgs: vec![QueryRequest::Bank(BankQuery::AllBalances { address }); 5],
+            callback: "callback".to_string(),
+            max_age: None,
+            projections: vec![],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("requester", &[]), msg).unwrap();
+        let sent = packets(&res);
+        assert_eq!(sent.len(), 3);
+        assert!(sent
+            .iter()
+            .all(|packet| packet.data.len() <= MAX_PACKET_SIZE));
+        let query = SHARDED_QUERIES.load(&deps.storage, 1).unwrap();
+        assert_eq!(query.queries, vec![2, 2, 1]);
+    }
+}
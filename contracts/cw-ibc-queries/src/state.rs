@@ -1,4 +1,195 @@
-use cw_storage_plus::Item;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    Addr, Binary, Coin, Empty, QueryRequest, StdResult, Storage, Timestamp, Uint128,
+};
+use cw_ibc_query::{AppVersion, IbcQueryResult, PacketMsgV2, PaginatedQuery, Quorum};
+use cw_storage_plus::{Item, Map};
+use cw_utils::Expiration;
+
+use crate::custom_query::HostQuery;
 
 pub const PENDING: Item<String> = Item::new("pending");
 pub const PACKET_LIFETIME: Item<u64> = Item::new("packet_lifetime");
+/// Most queries we answer in one packet, advertised in the handshake
+pub const MAX_BATCH_SIZE: Item<u32> = Item::new("max_batch_size");
+pub const ADMIN: Item<Addr> = Item::new("admin");
+/// gRPC paths the host is willing to run as Stargate queries
+pub const STARGATE_ALLOWLIST: Map<&str, Empty> = Map::new("stargate_allowlist");
+
+/// What we agreed on with the counterparty during the handshake
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ChannelInfo {
+    /// Capabilities both sides support
+    pub negotiated: AppVersion,
+}
+
+pub const CHANNELS: Map<&str, ChannelInfo> = Map::new("channels");
+
+/// Last id handed out to an outgoing query
+pub const QUERY_COUNT: Item<u64> = Item::new("query_count");
+
+pub fn next_query_id(store: &mut dyn Storage) -> StdResult<u64> {
+    let id = QUERY_COUNT.may_load(store)?.unwrap_or_default() + 1;
+    QUERY_COUNT.save(store, &id)?;
+    Ok(id)
+}
+
+/// Sequence of the last packet sent on a channel, only tracked on Polytone channels
+pub const PACKETS_SENT: Map<&str, u64> = Map::new("packets_sent");
+/// Polytone packets can't carry our query id and callback, so we remember the packets by
+/// sequence
+pub const PENDING_QUERIES: Map<(&str, u64), PacketMsgV2<HostQuery>> = Map::new("pending_queries");
+
+/// Queries sent to several channels, answered with one callback
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueryGroup {
+    pub callback: String,
+    /// Channel and query id of every packet in the group
+    pub members: Vec<(String, u64)>,
+    /// When the results that arrived so far may be delivered
+    pub deadline: Option<Timestamp>,
+    #[serde(default)]
+    pub quorum: Option<Quorum>,
+}
+
+pub const QUERY_GROUPS: Map<u64, QueryGroup> = Map::new("query_groups");
+/// Group of each query still waiting for a result
+pub const GROUP_MEMBERS: Map<u64, u64> = Map::new("group_members");
+/// Results that arrived so far, by group and channel
+pub const GROUP_RESULTS: Map<(u64, &str), IbcQueryResult> = Map::new("group_results");
+
+/// Queries sent again and again, by whoever claims the bounty
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Subscription {
+    pub owner: Addr,
+    pub channel_id: String,
+    pub msgs: Vec<QueryRequest<HostQuery>>,
+    pub callback: String,
+    /// Least seconds between two triggers
+    pub interval: u64,
+    pub expires: Expiration,
+    /// What's left to pay bounties from, refunded on cancel
+    pub deposit: Coin,
+    /// Paid to whoever triggers the subscription
+    pub bounty: Uint128,
+    pub paused: bool,
+    pub last_triggered: Option<Timestamp>,
+}
+
+/// Last id handed out to a subscription
+pub const SUBSCRIPTION_COUNT: Item<u64> = Item::new("subscription_count");
+pub const SUBSCRIPTIONS: Map<u64, Subscription> = Map::new("subscriptions");
+
+/// Seconds successful results stay in the cache, 0 turns caching off
+pub const CACHE_TTL: Item<u64> = Item::new("cache_ttl");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CachedResult {
+    pub data: Binary,
+    pub cached_at: Timestamp,
+}
+
+/// Latest successful result by channel and query hash
+pub const RESULT_CACHE: Map<(&str, &[u8]), CachedResult> = Map::new("result_cache");
+
+/// Packets whose answer identical requests can wait for, by channel and hash of their queries
+pub const IN_FLIGHT: Map<(&str, &[u8]), u64> = Map::new("in_flight");
+/// Callbacks of requests waiting for another packet, by its query id and their own
+pub const WAITING: Map<(u64, u64), String> = Map::new("waiting");
+
+/// Queued queries that trigger a flush, per channel with batching on
+pub const BATCHING: Map<&str, u32> = Map::new("batching");
+
+/// A request waiting in a channel's batching queue
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueuedQuery {
+    pub msgs: Vec<QueryRequest<HostQuery>>,
+    pub callback: String,
+}
+
+/// Queued requests by channel and query id
+pub const QUEUE: Map<(&str, u64), QueuedQuery> = Map::new("queue");
+
+/// One caller's share of a flushed packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BatchPart {
+    pub query_id: u64,
+    pub callback: String,
+    /// How many of the packet's queries are theirs, in order
+    pub len: u32,
+}
+
+/// The callers of each flushed packet in flight, by its query id
+pub const BATCHES: Map<u64, Vec<BatchPart>> = Map::new("batches");
+
+/// A request too big for one packet, answered with one callback
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ShardedQuery {
+    pub callback: String,
+    /// Query id of every shard, in the order of the request's queries
+    pub shards: Vec<u64>,
+    /// Number of queries in each shard
+    pub queries: Vec<u32>,
+}
+
+pub const SHARDED_QUERIES: Map<u64, ShardedQuery> = Map::new("sharded_queries");
+/// Query of each shard still in flight
+pub const SHARDS: Map<u64, u64> = Map::new("shards");
+/// Successful shards so far, by query and shard id
+pub const SHARD_RESULTS: Map<(u64, u64), IbcQueryResult> = Map::new("shard_results");
+
+/// A paginated query being followed page by page
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Pagination {
+    pub channel_id: String,
+    pub query: PaginatedQuery,
+    pub callback: String,
+    /// Pages answered so far
+    pub pages: u32,
+    /// Their items, as JSON
+    pub items: Vec<Binary>,
+    /// Total size of the items in bytes
+    #[serde(default)]
+    pub size: u64,
+}
+
+pub const PAGINATIONS: Map<u64, Pagination> = Map::new("paginations");
+/// Paginated query of each page in flight
+pub const PAGES: Map<u64, u64> = Map::new("pages");
+
+/// Queries registered by the admin under a stable name, see `fill_params`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct View {
+    pub queries: Vec<QueryRequest<HostQuery>>,
+    /// Whether later queries use earlier results, see `resolve_query`
+    pub chained: bool,
+}
+
+pub const VIEWS: Map<&str, View> = Map::new("views");
+
+/// Contracts answering `PacketMsg::Responder` requests, by the name requesters use
+pub const RESPONDERS: Map<&str, Addr> = Map::new("responders");
+
+/// Connection of each channel, to tell which chain a packet comes from
+pub const CONNECTIONS: Map<&str, String> = Map::new("connections");
+
+/// Who may query a contract from other chains
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Queryability {
+    /// Any counterparty, the default
+    Public,
+    /// No counterparty
+    Private,
+    /// Only packets arriving on these channels, or on any channel of these connections to
+    /// other chains
+    Restricted {
+        channels: Vec<String>,
+        connections: Vec<String>,
+    },
+}
+
+/// Contracts that aren't public, set by themselves or their admin
+pub const QUERYABILITY: Map<&Addr, Queryability> = Map::new("queryability");
@@ -0,0 +1,366 @@
+use cosmwasm_std::{BankMsg, Coin, Deps, DepsMut, Env, MessageInfo, Order, Response, StdResult};
+use cw_storage_plus::Bound;
+
+use crate::contract::send_query;
+use crate::error::ContractError;
+use crate::ibc::channel_version;
+use crate::msg::{SubscribeMsg, SubscriptionResponse, SubscriptionsResponse};
+use crate::state::{Subscription, SUBSCRIPTIONS, SUBSCRIPTION_COUNT};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+pub fn execute_subscribe(
+    deps: DepsMut,
+    info: MessageInfo,
+    msg: SubscribeMsg,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&msg.callback)?;
+    if msg.interval == 0 {
+        return Err(ContractError::ZeroInterval);
+    }
+    // fail now rather than on every trigger
+    channel_version(deps.as_ref(), &msg.channel_id)?.supports_all(&msg.msgs)?;
+    let deposit = cw_utils::one_coin(&info)?;
+
+    let id = SUBSCRIPTION_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    SUBSCRIPTION_COUNT.save(deps.storage, &id)?;
+    let subscription = Subscription {
+        owner: info.sender,
+        channel_id: msg.channel_id,
+        msgs: msg.msgs,
+        callback: msg.callback,
+        interval: msg.interval,
+        expires: msg.expires.unwrap_or_default(),
+        deposit,
+        bounty: msg.bounty,
+        paused: false,
+        last_triggered: None,
+    };
+    SUBSCRIPTIONS.save(deps.storage, id, &subscription)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "subscribe")
+        .add_attribute("subscription_id", id.to_string()))
+}
+
+/// Tops up the deposit, in the denom the subscription was created with
+pub fn execute_fund_subscription(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let mut subscription = load(deps.as_ref(), id)?;
+    let amount = cw_utils::must_pay(&info, &subscription.deposit.denom)?;
+    subscription.deposit.amount += amount;
+    SUBSCRIPTIONS.save(deps.storage, id, &subscription)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_subscription")
+        .add_attribute("subscription_id", id.to_string())
+        .add_attribute("deposit", subscription.deposit.to_string()))
+}
+
+/// Sends the subscription's queries once its interval passed, paying the caller the bounty
+pub fn execute_trigger(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let mut subscription = load(deps.as_ref(), id)?;
+    if subscription.paused {
+        return Err(ContractError::SubscriptionPaused(id));
+    }
+    if subscription.expires.is_expired(&env.block) {
+        return Err(ContractError::SubscriptionExpired(id));
+    }
+    if let Some(last) = subscription.last_triggered {
+        let due = last.plus_seconds(subscription.interval);
+        if env.block.time < due {
+            return Err(ContractError::SubscriptionNotDue { id, due });
+        }
+    }
+    subscription.deposit.amount = subscription
+        .deposit
+        .amount
+        .checked_sub(subscription.bounty)
+        .map_err(|_| ContractError::SubscriptionUnfunded(id))?;
+    subscription.last_triggered = Some(env.block.time);
+    SUBSCRIPTIONS.save(deps.storage, id, &subscription)?;
+
+    let (query_id, msg) = send_query(
+        deps,
+        &env,
+        subscription.owner.into(),
+        subscription.channel_id,
+        subscription.msgs,
+        subscription.callback,
+    )?;
+    let mut res = Response::new()
+        .add_message(msg)
+        .add_attribute("action", "trigger")
+        .add_attribute("subscription_id", id.to_string())
+        .add_attribute("query_id", query_id.to_string());
+    if !subscription.bounty.is_zero() {
+        res = res.add_message(BankMsg::Send {
+            to_address: info.sender.into(),
+            amount: vec![Coin {
+                denom: subscription.deposit.denom,
+                amount: subscription.bounty,
+            }],
+        });
+    }
+    Ok(res)
+}
+
+/// Owner only, stops or restarts triggers
+pub fn execute_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let mut subscription = load(deps.as_ref(), id)?;
+    if subscription.owner != info.sender {
+        return Err(ContractError::Unauthorized);
+    }
+    subscription.paused = paused;
+    SUBSCRIPTIONS.save(deps.storage, id, &subscription)?;
+
+    let action = if paused {
+        "pause_subscription"
+    } else {
+        "resume_subscription"
+    };
+    Ok(Response::new()
+        .add_attribute("action", action)
+        .add_attribute("subscription_id", id.to_string()))
+}
+
+/// Owner only, removes the subscription and refunds what's left of the deposit
+pub fn execute_cancel(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let subscription = load(deps.as_ref(), id)?;
+    if subscription.owner != info.sender {
+        return Err(ContractError::Unauthorized);
+    }
+    SUBSCRIPTIONS.remove(deps.storage, id);
+
+    let mut res = Response::new()
+        .add_attribute("action", "cancel_subscription")
+        .add_attribute("subscription_id", id.to_string());
+    if !subscription.deposit.amount.is_zero() {
+        res = res.add_message(BankMsg::Send {
+            to_address: subscription.owner.into(),
+            amount: vec![subscription.deposit],
+        });
+    }
+    Ok(res)
+}
+
+fn load(deps: Deps, id: u64) -> Result<Subscription, ContractError> {
+    SUBSCRIPTIONS
+        .may_load(deps.storage, id)?
+        .ok_or(ContractError::UnknownSubscription(id))
+}
+
+pub fn query_subscription(deps: Deps, id: u64) -> StdResult<SubscriptionResponse> {
+    let subscription = SUBSCRIPTIONS.load(deps.storage, id)?;
+    Ok(SubscriptionResponse {
+        subscription_id: id,
+        subscription,
+    })
+}
+
+pub fn query_subscriptions(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<SubscriptionsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let subscriptions = SUBSCRIPTIONS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            item.map(|(subscription_id, subscription)| SubscriptionResponse {
+                subscription_id,
+                subscription,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(SubscriptionsResponse { subscriptions })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, BankQuery, CosmosMsg, Env, IbcMsg, QueryRequest};
+    use cw_ibc_query::AppVersion;
+    use cw_utils::PaymentError;
+
+    use crate::contract::{execute, query};
+    use crate::msg::{ExecuteMsg, QueryMsg};
+    use crate::testing::{setup, setup_channel};
+
+    use super::*;
+
+    const OWNER: &str = "owner";
+    const KEEPER: &str = "keeper";
+    const CHANNEL: &str = "channel-3";
+
+    fn subscribe(interval: u64) -> ExecuteMsg {
+        ExecuteMsg::Subscribe(SubscribeMsg {
+            channel_id: CHANNEL.to_string(),
+            msgs: vec![QueryRequest::Bank(BankQuery::AllBalances {
+                address: "treasury".to_string(),
+            })],
+            callback: "dashboard".to_string(),
+            interval,
+            expires: None,
+            bounty: 40u128.into(),
+        })
+    }
+
+    const TRIGGER: ExecuteMsg = ExecuteMsg::Trigger { subscription_id: 1 };
+
+    /// Subscribes every 300 seconds with a deposit of 100, the bounty is 40
+    fn setup_subscription(mut deps: DepsMut) {
+        setup(deps.branch(), OWNER);
+        setup_channel(deps.branch(), CHANNEL, AppVersion::new(10, None));
+        let funded = mock_info(OWNER, &coins(100, "ujuno"));
+        execute(deps, mock_env(), funded, subscribe(300)).unwrap();
+    }
+
+    /// Triggers twice, leaving 20 in the deposit. Returns when the next trigger is due.
+    fn trigger_twice(deps: &mut DepsMut) -> Env {
+        let mut env = mock_env();
+        execute(deps.branch(), env.clone(), mock_info(KEEPER, &[]), TRIGGER).unwrap();
+        env.block.time = env.block.time.plus_seconds(300);
+        execute(deps.branch(), env.clone(), mock_info(KEEPER, &[]), TRIGGER).unwrap();
+        env.block.time = env.block.time.plus_seconds(300);
+        env
+    }
+
+    #[test]
+    fn subscribing_needs_interval_and_deposit() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), OWNER);
+        setup_channel(deps.as_mut(), CHANNEL, AppVersion::new(10, None));
+
+        let funded = mock_info(OWNER, &coins(100, "ujuno"));
+        let err = execute(deps.as_mut(), mock_env(), funded, subscribe(0));
+        assert_eq!(err.unwrap_err(), ContractError::ZeroInterval);
+        let unfunded = mock_info(OWNER, &[]);
+        let err = execute(deps.as_mut(), mock_env(), unfunded, subscribe(300));
+        assert_eq!(err.unwrap_err(), PaymentError::NoFunds {}.into());
+    }
+
+    #[test]
+    fn keepers_trigger_for_a_bounty() {
+        let mut deps = mock_dependencies();
+        setup_subscription(deps.as_mut());
+
+        // the first trigger sends the queries and pays the keeper
+        let keeper = mock_info(KEEPER, &[]);
+        let res = execute(deps.as_mut(), mock_env(), keeper, TRIGGER).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert!(matches!(
+            res.messages[0].msg,
+            CosmosMsg::Ibc(IbcMsg::SendPacket { .. })
+        ));
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: KEEPER.to_string(),
+                amount: coins(40, "ujuno"),
+            })
+        );
+    }
+
+    #[test]
+    fn triggers_wait_for_the_interval() {
+        let mut deps = mock_dependencies();
+        setup_subscription(deps.as_mut());
+
+        let mut env = mock_env();
+        let keeper = mock_info(KEEPER, &[]);
+        execute(deps.as_mut(), env.clone(), keeper.clone(), TRIGGER).unwrap();
+        let err = execute(deps.as_mut(), env.clone(), keeper.clone(), TRIGGER);
+        let due = env.block.time.plus_seconds(300);
+        assert_eq!(
+            err.unwrap_err(),
+            ContractError::SubscriptionNotDue { id: 1, due }
+        );
+        env.block.time = due;
+        execute(deps.as_mut(), env, keeper, TRIGGER).unwrap();
+    }
+
+    #[test]
+    fn unfunded_subscriptions_wait_for_a_top_up() {
+        let mut deps = mock_dependencies();
+        setup_subscription(deps.as_mut());
+        let env = trigger_twice(&mut deps.as_mut());
+
+        // 20 are left, not enough for another bounty
+        let err = execute(deps.as_mut(), env.clone(), mock_info(KEEPER, &[]), TRIGGER);
+        assert_eq!(err.unwrap_err(), ContractError::SubscriptionUnfunded(1));
+        let fund = ExecuteMsg::FundSubscription { subscription_id: 1 };
+        let top_up = mock_info(KEEPER, &coins(30, "ujuno"));
+        execute(deps.as_mut(), env.clone(), top_up, fund).unwrap();
+        execute(deps.as_mut(), env, mock_info(KEEPER, &[]), TRIGGER).unwrap();
+    }
+
+    #[test]
+    fn only_the_owner_pauses() {
+        let mut deps = mock_dependencies();
+        setup_subscription(deps.as_mut());
+
+        let pause = ExecuteMsg::PauseSubscription { subscription_id: 1 };
+        let keeper = mock_info(KEEPER, &[]);
+        let err = execute(deps.as_mut(), mock_env(), keeper.clone(), pause.clone());
+        assert_eq!(err.unwrap_err(), ContractError::Unauthorized);
+        execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), pause).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), keeper, TRIGGER);
+        assert_eq!(err.unwrap_err(), ContractError::SubscriptionPaused(1));
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::Subscriptions {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let res: SubscriptionsResponse = from_binary(&res).unwrap();
+        let subscription = &res.subscriptions[0].subscription;
+        assert!(subscription.paused);
+        assert_eq!(subscription.deposit, coin(100, "ujuno"));
+    }
+
+    #[test]
+    fn cancelling_refunds_the_rest() {
+        let mut deps = mock_dependencies();
+        setup_subscription(deps.as_mut());
+        let env = trigger_twice(&mut deps.as_mut());
+
+        let cancel = ExecuteMsg::CancelSubscription { subscription_id: 1 };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), cancel).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(20, "ujuno"),
+            })
+        );
+        assert!(!SUBSCRIPTIONS.has(&deps.storage, 1));
+    }
+}
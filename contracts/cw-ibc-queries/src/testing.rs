@@ -0,0 +1,77 @@
+use cosmwasm_std::testing::{mock_env, mock_ibc_packet_timeout, mock_info};
+use cosmwasm_std::{
+    BankQuery, CosmosMsg, DepsMut, Empty, IbcMsg, IbcPacket, QueryRequest, Response,
+};
+use cw_ibc_query::{AppVersion, Encoding};
+
+use crate::contract::instantiate;
+use crate::msg::{ExecuteMsg, InstantiateMsg};
+use crate::state::{ChannelInfo, CHANNELS};
+
+/// Instantiates the contract, `admin` becomes its admin
+pub fn setup(deps: DepsMut, admin: &str) {
+    let msg = InstantiateMsg {
+        packet_lifetime: 60,
+        admin: None,
+        max_batch_size: None,
+        cache_ttl: None,
+    };
+    instantiate(deps, mock_env(), mock_info(admin, &[]), msg).unwrap();
+}
+
+/// A v2 channel speaking JSON with every capability
+pub fn json_v2(max_batch_size: u32) -> AppVersion {
+    AppVersion {
+        encodings: vec![Encoding::Json],
+        ..AppVersion::new(max_batch_size, None)
+    }
+}
+
+/// Records `channel` as open with `negotiated`, as the handshake would
+pub fn setup_channel(deps: DepsMut, channel: &str, negotiated: AppVersion) {
+    CHANNELS
+        .save(deps.storage, channel, &ChannelInfo { negotiated })
+        .unwrap();
+}
+
+/// An `IbcQuery` for the balances of `addresses`
+pub fn balance_query(
+    channel_id: &str,
+    addresses: &[impl ToString],
+    callback: &str,
+    max_age: Option<u64>,
+) -> ExecuteMsg {
+    ExecuteMsg::IbcQuery {
+        channel_id: channel_id.to_string(),
+        msgs: addresses
+            .iter()
+            .map(|address| {
+                QueryRequest::Bank(BankQuery::AllBalances {
+                    address: address.to_string(),
+                })
+            })
+            .collect(),
+        callback: callback.to_string(),
+        max_age,
+        projections: vec![],
+    }
+}
+
+/// The packets a response sends, as the relayer would deliver them back
+pub fn packets(res: &Response) -> Vec<IbcPacket> {
+    res.messages
+        .iter()
+        .filter_map(|msg| match &msg.msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket {
+                channel_id, data, ..
+            }) => {
+                let mut packet = mock_ibc_packet_timeout(channel_id, &Empty {})
+                    .unwrap()
+                    .packet;
+                packet.data = data.clone();
+                Some(packet)
+            }
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,245 @@
+use cosmwasm_std::{Deps, DepsMut, MessageInfo, Order, QueryRequest, Response, StdResult};
+use cw_ibc_query::{fill_params, SimpleIcaError, MAX_CHAIN_STEPS};
+use cw_storage_plus::Bound;
+
+use crate::contract::assert_admin;
+use crate::custom_query::HostQuery;
+use crate::error::ContractError;
+use crate::msg::{ViewResponse, ViewsResponse};
+use crate::state::{View, MAX_BATCH_SIZE, VIEWS};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+
+/// Admin only, registers `queries` under `name`, replacing the view's old queries
+pub fn execute_set_view(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    queries: Vec<QueryRequest<HostQuery>>,
+    chained: bool,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    if queries.is_empty() {
+        return Err(ContractError::EmptyView(name));
+    }
+    // the view must fit in what we answer in one packet
+    let max_batch_size = MAX_BATCH_SIZE.load(deps.storage)?;
+    if queries.len() > max_batch_size as usize {
+        return Err(SimpleIcaError::BatchTooLarge(max_batch_size).into());
+    }
+    if chained && queries.len() > MAX_CHAIN_STEPS as usize {
+        return Err(SimpleIcaError::ChainTooLong(MAX_CHAIN_STEPS).into());
+    }
+    VIEWS.save(deps.storage, &name, &View { queries, chained })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_view")
+        .add_attribute("name", name))
+}
+
+/// Admin only
+pub fn execute_remove_view(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    assert_admin(deps.as_ref(), &info)?;
+    if !VIEWS.has(deps.storage, &name) {
+        return Err(ContractError::UnknownView(name));
+    }
+    VIEWS.remove(deps.storage, &name);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_view")
+        .add_attribute("name", name))
+}
+
+/// The view a packet asks for, with the requester's params filled in. Chained views keep their
+/// placeholders, `run_chain` fills in params and earlier results together.
+pub fn resolve_view(
+    deps: Deps,
+    name: &str,
+    params: &[(String, String)],
+) -> Result<View, ContractError> {
+    let view = VIEWS
+        .may_load(deps.storage, name)?
+        .ok_or_else(|| ContractError::UnknownView(name.to_string()))?;
+    let queries: Vec<_> = view
+        .queries
+        .iter()
+        .map(|query| fill_params(query, params))
+        .collect::<Result<_, _>>()
+        .map_err(|err| ContractError::InvalidViewParams {
+            name: name.to_string(),
+            err,
+        })?;
+    if view.chained {
+        return Ok(view);
+    }
+    Ok(View {
+        queries,
+        chained: false,
+    })
+}
+
+pub fn query_views(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ViewsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let views = VIEWS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(name, view)| ViewResponse { name, view }))
+        .collect::<StdResult<_>>()?;
+    Ok(ViewsResponse { views })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_dependencies_with_balances, mock_env, mock_ibc_packet_recv,
+        mock_info,
+    };
+    use cosmwasm_std::{coins, from_binary, to_binary, BalanceResponse, BankQuery, Empty};
+    use cw_ibc_query::{IbcQueryResult, PacketMsg, PacketMsgV2, ProtocolVersion, WireFormat};
+
+    use crate::contract::{execute, query};
+    use crate::ibc::ibc_packet_receive;
+    use crate::msg::{ExecuteMsg, QueryMsg};
+    use crate::testing::{json_v2, setup, setup_channel};
+
+    use super::*;
+
+    const CHANNEL: &str = "channel-3";
+
+    fn view_packet(name: &str, params: &[(&str, &str)]) -> PacketMsgV2 {
+        PacketMsgV2 {
+            id: 4,
+            sender: "sender".to_string(),
+            msg: PacketMsg::View {
+                name: name.to_string(),
+                params: params
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                callback: "callback".to_string(),
+            },
+        }
+    }
+
+    fn set_balance_view() -> ExecuteMsg {
+        ExecuteMsg::SetView {
+            name: "balance".to_string(),
+            queries: vec![QueryRequest::Bank(BankQuery::Balance {
+                address: "${address}".to_string(),
+                denom: "ujuno".to_string(),
+            })],
+            chained: false,
+        }
+    }
+
+    /// Registers the balance view on a host where the treasury holds 42
+    fn setup_view(mut deps: DepsMut) {
+        setup(deps.branch(), "admin");
+        setup_channel(deps.branch(), CHANNEL, json_v2(10));
+        execute(
+            deps,
+            mock_env(),
+            mock_info("admin", &[]),
+            set_balance_view(),
+        )
+        .unwrap();
+    }
+
+    fn receive(deps: DepsMut, packet: PacketMsgV2) -> IbcQueryResult {
+        let mut msg = mock_ibc_packet_recv(CHANNEL, &Empty {}).unwrap();
+        msg.packet.data = to_binary(&packet).unwrap();
+        let res = ibc_packet_receive(deps, mock_env(), msg).unwrap();
+        WireFormat::from(ProtocolVersion::V2)
+            .decode_ack(&res.acknowledgement)
+            .unwrap()
+    }
+
+    #[test]
+    fn only_admin_sets_views() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut(), "admin");
+
+        let requester = mock_info("requester", &[]);
+        let err = execute(deps.as_mut(), mock_env(), requester, set_balance_view());
+        assert_eq!(err.unwrap_err(), ContractError::Unauthorized);
+        let admin = mock_info("admin", &[]);
+        execute(deps.as_mut(), mock_env(), admin, set_balance_view()).unwrap();
+        let msg = QueryMsg::Views {
+            start_after: None,
+            limit: None,
+        };
+        let views: ViewsResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(views.views[0].name, "balance");
+    }
+
+    #[test]
+    fn hosts_run_registered_views() {
+        let mut deps = mock_dependencies_with_balances(&[("treasury", &coins(42, "ujuno"))]);
+        setup_view(deps.as_mut());
+
+        let packet = view_packet("balance", &[("address", "treasury")]);
+        let results = match receive(deps.as_mut(), packet) {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        let balance: BalanceResponse = from_binary(&results[0].clone().unwrap()).unwrap();
+        assert_eq!(balance.amount.amount.u128(), 42);
+    }
+
+    #[test]
+    fn views_need_their_params() {
+        let mut deps = mock_dependencies();
+        setup_view(deps.as_mut());
+
+        assert_eq!(
+            receive(deps.as_mut(), view_packet("balance", &[])),
+            IbcQueryResult::Error(
+                "simple-ica-v2: Invalid params for view balance: Missing param address".to_string()
+            )
+        );
+        assert_eq!(
+            receive(deps.as_mut(), view_packet("supply", &[])),
+            IbcQueryResult::Error("simple-ica-v2: Unknown view supply".to_string())
+        );
+    }
+
+    #[test]
+    fn params_stay_literal_in_chained_views() {
+        let mut deps = mock_dependencies_with_balances(&[("treasury", &coins(42, "ujuno"))]);
+        setup(deps.as_mut(), "admin");
+        setup_channel(deps.as_mut(), CHANNEL, json_v2(10));
+        let balance = |denom: &str| {
+            QueryRequest::Bank(BankQuery::Balance {
+                address: "treasury".to_string(),
+                denom: denom.to_string(),
+            })
+        };
+        let msg = ExecuteMsg::SetView {
+            name: "balances".to_string(),
+            queries: vec![balance("ujuno"), balance("${denom}")],
+            chained: true,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+
+        // a param naming an earlier result is not resolved
+        let packet = view_packet("balances", &[("denom", "${0/amount/denom}")]);
+        let results = match receive(deps.as_mut(), packet) {
+            IbcQueryResult::Ok(results) => results,
+            other => panic!("unexpected ack {:?}", other),
+        };
+        let balance: BalanceResponse = from_binary(&results[1].clone().unwrap()).unwrap();
+        assert_eq!(balance.amount.denom, "${0/amount/denom}");
+        assert_eq!(balance.amount.amount.u128(), 0);
+    }
+}
@@ -2,11 +2,13 @@ use cosmwasm_std::{
     entry_point, to_binary, Deps, DepsMut, Env, IbcPacketAckMsg, MessageInfo, QueryResponse,
     Response, StdResult,
 };
-use cw_ibc_query::ReceiveIbcResponseMsg;
+use cw_ibc_query::{ReceiveIbcQueryResponseMsg, ReceiveIbcResponseMsg};
 
 use crate::error::ContractError;
 use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{IbcQueryResultResponse, LATEST_QUERIES};
+use crate::state::{
+    IbcQueryResponseResponse, IbcQueryResultResponse, LATEST_QUERIES, LATEST_RESPONSES,
+};
 
 #[entry_point]
 pub fn instantiate(
@@ -31,6 +33,7 @@ pub fn execute(
         ExecuteMsg::ReceiveIbcResponse(ReceiveIbcResponseMsg { msg }) => {
             execute_receive(deps, env, info, msg)
         }
+        ExecuteMsg::ReceiveIbcQueryResponse(msg) => execute_receive_response(deps, env, info, msg),
     }
 }
 
@@ -54,12 +57,32 @@ pub fn execute_receive(
     Ok(Response::default())
 }
 
+pub fn execute_receive_response(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    msg: ReceiveIbcQueryResponseMsg,
+) -> Result<Response, ContractError> {
+    LATEST_RESPONSES.save(
+        deps.storage,
+        &msg.channel_id.clone(),
+        &IbcQueryResponseResponse {
+            last_update_time: env.block.time,
+            response: msg,
+        },
+    )?;
+    Ok(Response::default())
+}
+
 #[entry_point]
 pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<QueryResponse> {
     match msg {
         QueryMsg::LatestQueryResult { channel_id } => {
             to_binary(&query_latest_ibc_query_result(deps, channel_id)?)
         }
+        QueryMsg::LatestQueryResponse { channel_id } => {
+            to_binary(&LATEST_RESPONSES.load(deps.storage, &channel_id)?)
+        }
     }
 }
 
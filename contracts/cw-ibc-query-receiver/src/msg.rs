@@ -1,4 +1,4 @@
-use cw_ibc_query::ReceiveIbcResponseMsg;
+use cw_ibc_query::{ReceiveIbcQueryResponseMsg, ReceiveIbcResponseMsg};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -10,6 +10,7 @@ pub struct InstantiateMsg {}
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     ReceiveIbcResponse(ReceiveIbcResponseMsg),
+    ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -17,4 +18,6 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     // Get latest query
     LatestQueryResult { channel_id: String },
+    // Get latest decoded response from a v2 channel
+    LatestQueryResponse { channel_id: String },
 }
@@ -2,6 +2,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use cosmwasm_std::{IbcPacketAckMsg, Timestamp};
+use cw_ibc_query::ReceiveIbcQueryResponseMsg;
 use cw_storage_plus::Map;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -11,3 +12,11 @@ pub struct IbcQueryResultResponse {
     pub response: IbcPacketAckMsg,
 }
 pub const LATEST_QUERIES: Map<&str, IbcQueryResultResponse> = Map::new("queries");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IbcQueryResponseResponse {
+    /// last block the response was updated
+    pub last_update_time: Timestamp,
+    pub response: ReceiveIbcQueryResponseMsg,
+}
+pub const LATEST_RESPONSES: Map<&str, IbcQueryResponseResponse> = Map::new("responses");
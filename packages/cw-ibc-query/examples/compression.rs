@@ -0,0 +1,105 @@
+//! Compares packet and ack sizes with and without compression for some typical batches.
+//!
+//! Run with `cargo run --release --example compression`. Relayer gas grows with the packet and
+//! ack size, while the contracts pay for (de)compression, so the time per batch is shown too.
+
+use std::time::Instant;
+
+use cosmwasm_std::{
+    to_binary, Addr, AllBalanceResponse, AllDelegationsResponse, BankQuery, Binary, Coin,
+    ContractResult, Delegation, Empty, QueryRequest, StakingQuery,
+};
+use cw_ibc_query::{Compression, Encoding, PacketMsg, PacketMsgV2, ProtocolVersion, WireFormat};
+
+const ROUNDS: u32 = 100;
+
+fn balances(coins: usize) -> Binary {
+    let amount = (0..coins)
+        .map(|i| Coin::new(1_000_000 + i as u128, format!("ibc/{:064X}", i)))
+        .collect();
+    to_binary(&AllBalanceResponse { amount }).unwrap()
+}
+
+fn delegations(count: usize) -> Binary {
+    let delegations = (0..count)
+        .map(|i| Delegation {
+            delegator: Addr::unchecked("osmo1qyqszqgpqyqszqgpqyqszqgpqyqszqgpjnp7du"),
+            validator: format!("osmovaloper1{:038}", i),
+            amount: Coin::new(42_000_000 + i as u128, "uosmo"),
+        })
+        .collect();
+    to_binary(&AllDelegationsResponse { delegations }).unwrap()
+}
+
+fn packet(queries: usize) -> PacketMsgV2 {
+    PacketMsgV2 {
+        id: 1,
+        sender: "osmo1qyqszqgpqyqszqgpqyqszqgpqyqszqgpjnp7du".to_string(),
+        msg: PacketMsg::IbcQuery {
+            msgs: (0..queries)
+                .map(|i| {
+                    if i % 2 == 0 {
+                        QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
+                            address: format!("osmo1{:038}", i),
+                        })
+                    } else {
+                        QueryRequest::Staking(StakingQuery::AllDelegations {
+                            delegator: format!("osmo1{:038}", i),
+                        })
+                    }
+                })
+                .collect(),
+            callback: "osmo1callback".to_string(),
+            projections: vec![],
+            chained: false,
+            aggregation: None,
+        },
+    }
+}
+
+fn main() {
+    let batches = [
+        ("1 small balance", 1, vec![balances(1)]),
+        ("10 balances of 20 coins", 10, vec![balances(20); 10]),
+        ("50 delegation lists of 30", 50, vec![delegations(30); 50]),
+    ];
+    println!(
+        "{:<28} {:<6} {:>10} {:>10} {:>10} {:>10} {:>12}",
+        "batch", "enc", "packet", "zpacket", "ack", "zack", "us/roundtrip"
+    );
+    for (name, queries, results) in batches {
+        let results: Vec<_> = results.into_iter().map(ContractResult::Ok).collect();
+        let packet = packet(queries);
+        for encoding in [Encoding::Json, Encoding::Proto] {
+            let plain = WireFormat {
+                version: ProtocolVersion::V2,
+                encoding,
+                compression: None,
+            };
+            let compressed = WireFormat {
+                compression: Some(Compression::Deflate),
+                ..plain
+            };
+
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                let data = compressed.encode_packet(&packet).unwrap();
+                compressed.decode_packet::<Empty>(&data).unwrap();
+                let ack = compressed.encode_ack(1, results.clone()).unwrap();
+                compressed.decode_ack(&ack).unwrap();
+            }
+            let micros = start.elapsed().as_micros() / ROUNDS as u128;
+
+            println!(
+                "{:<28} {:<6} {:>10} {:>10} {:>10} {:>10} {:>12}",
+                name,
+                format!("{:?}", encoding).to_lowercase(),
+                plain.encode_packet(&packet).unwrap().len(),
+                compressed.encode_packet(&packet).unwrap().len(),
+                plain.encode_ack(1, results.clone()).unwrap().len(),
+                compressed.encode_ack(1, results.clone()).unwrap().len(),
+                micros,
+            );
+        }
+    }
+}
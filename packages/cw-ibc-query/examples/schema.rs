@@ -3,7 +3,10 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use cw_ibc_query::{IbcQueryResponse, PacketMsg, StdAck};
+use cw_ibc_query::{
+    AppVersion, IbcQueryResponse, IbcQueryResponseV2, PacketMsg, PacketMsgV2, QueryAck,
+    ReceiveIbcQueryGroupResponseMsg, ReceiveIbcQueryResponseMsg, StdAck,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -14,4 +17,10 @@ fn main() {
     export_schema(&schema_for!(PacketMsg), &out_dir);
     export_schema(&schema_for!(StdAck), &out_dir);
     export_schema(&schema_for!(IbcQueryResponse), &out_dir);
+    export_schema(&schema_for!(AppVersion), &out_dir);
+    export_schema(&schema_for!(PacketMsgV2), &out_dir);
+    export_schema(&schema_for!(IbcQueryResponseV2), &out_dir);
+    export_schema(&schema_for!(QueryAck), &out_dir);
+    export_schema(&schema_for!(ReceiveIbcQueryResponseMsg), &out_dir);
+    export_schema(&schema_for!(ReceiveIbcQueryGroupResponseMsg), &out_dir);
 }
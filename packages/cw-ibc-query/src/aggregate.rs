@@ -0,0 +1,241 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{to_binary, Binary, ContractResult, Decimal, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::checks::SimpleIcaError;
+use crate::json::Json;
+
+/// 10^18, the scale of a `Decimal`'s atomics
+const DECIMAL_SCALE: Uint128 = Uint128::new(1_000_000_000_000_000_000);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateOp {
+    Sum,
+    Min,
+    Max,
+    Count,
+    /// The middle value, or the mean of the two middle values, rounded down
+    Median,
+}
+
+/// Folds a number out of every result of a packet into one value on the host, the ack then
+/// carries only an `AggregateResult`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Aggregation {
+    pub op: AggregateOp,
+    /// JSON pointer to the number in each result, like `/balance/amount`. Numbers may be JSON
+    /// numbers or strings holding a `Uint128` or `Decimal`.
+    pub pointer: String,
+}
+
+/// The only result of an aggregated packet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AggregateResult {
+    /// A `Uint128` if all inputs were whole numbers, a `Decimal` otherwise. None for the min,
+    /// max or median of no inputs.
+    pub value: Option<String>,
+    /// How many results went into the value
+    pub inputs: u32,
+    /// Results that were left out, and why
+    pub errors: Vec<InputError>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InputError {
+    /// Index of the query in the packet
+    pub index: u32,
+    pub error: String,
+}
+
+enum Amount {
+    Whole(Uint128),
+    Fraction(Decimal),
+}
+
+impl Aggregation {
+    pub fn validate(&self) -> Result<(), SimpleIcaError> {
+        if !self.pointer.is_empty() && !self.pointer.starts_with('/') {
+            return Err(SimpleIcaError::InvalidAggregation(format!(
+                "{} is not a JSON pointer",
+                self.pointer
+            )));
+        }
+        Ok(())
+    }
+
+    /// The aggregate of the results, as the single result of the packet. Unusable results are
+    /// reported in it, overflowing the value fails it.
+    pub fn apply(&self, results: &[ContractResult<Binary>]) -> ContractResult<Binary> {
+        let mut amounts = vec![];
+        let mut errors = vec![];
+        for (index, result) in results.iter().enumerate() {
+            match self.read_amount(result) {
+                Ok(amount) => amounts.push((index, amount)),
+                Err(error) => errors.push(InputError {
+                    index: index as u32,
+                    error,
+                }),
+            }
+        }
+
+        // whole numbers are scaled up to decimals if any input has a fraction
+        let fraction = amounts
+            .iter()
+            .any(|(_, a)| matches!(a, Amount::Fraction(_)));
+        let mut values = Vec::with_capacity(amounts.len());
+        for (index, amount) in amounts {
+            match (amount, fraction) {
+                (Amount::Whole(value), false) => values.push(value),
+                (Amount::Fraction(value), _) => values.push(value.atomics()),
+                (Amount::Whole(value), true) => match value.checked_mul(DECIMAL_SCALE) {
+                    Ok(value) => values.push(value),
+                    Err(_) => errors.push(InputError {
+                        index: index as u32,
+                        error: "Value is too large for a Decimal".to_string(),
+                    }),
+                },
+            }
+        }
+        errors.sort_by_key(|e| e.index);
+
+        let value = match self.fold(&mut values) {
+            Ok(value) => value,
+            Err(err) => return ContractResult::Err(err),
+        };
+        let value = match (value, fraction && self.op != AggregateOp::Count) {
+            (Some(value), true) => Some(Decimal::new(value).to_string()),
+            (value, _) => value.map(|v| v.to_string()),
+        };
+        to_binary(&AggregateResult {
+            value,
+            inputs: values.len() as u32,
+            errors,
+        })
+        .map_err(|err| err.to_string())
+        .into()
+    }
+
+    fn read_amount(&self, result: &ContractResult<Binary>) -> Result<Amount, String> {
+        let data = match result {
+            ContractResult::Ok(data) => data,
+            ContractResult::Err(err) => return Err(err.clone()),
+        };
+        let json = Json::from_slice(data).map_err(|_| "Result is not JSON".to_string())?;
+        let not_a_number = || format!("Value at {} is not a number", self.pointer);
+        match json.pointer(&self.pointer) {
+            Some(Json::Number(value)) => u128::try_from(*value)
+                .map(|v| Amount::Whole(v.into()))
+                .map_err(|_| format!("Value at {} is negative", self.pointer)),
+            Some(Json::String(value)) if value.contains('.') => Decimal::from_str(value)
+                .map(Amount::Fraction)
+                .map_err(|_| not_a_number()),
+            Some(Json::String(value)) => Uint128::from_str(value)
+                .map(Amount::Whole)
+                .map_err(|_| not_a_number()),
+            Some(_) => Err(not_a_number()),
+            None => Err(format!("Path {} not found in result", self.pointer)),
+        }
+    }
+
+    fn fold(&self, values: &mut [Uint128]) -> Result<Option<Uint128>, String> {
+        let overflow = |_| "Overflow computing the sum of the results".to_string();
+        Ok(match self.op {
+            AggregateOp::Count => Some(Uint128::from(values.len() as u128)),
+            AggregateOp::Sum => Some(values.iter().try_fold(Uint128::zero(), |sum, value| {
+                sum.checked_add(*value).map_err(overflow)
+            })?),
+            AggregateOp::Min => values.iter().min().copied(),
+            AggregateOp::Max => values.iter().max().copied(),
+            AggregateOp::Median => {
+                values.sort_unstable();
+                let mid = values.len() / 2;
+                match values.len() {
+                    0 => None,
+                    len if len % 2 == 1 => Some(values[mid]),
+                    // halves first, so the sum can't overflow
+                    _ => {
+                        let (a, b) = (values[mid - 1], values[mid]);
+                        let carry = Uint128::from((a.u128() % 2 + b.u128() % 2) / 2);
+                        Some(a / Uint128::new(2) + b / Uint128::new(2) + carry)
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::from_binary;
+
+    use super::*;
+
+    fn results(data: &[&str]) -> Vec<ContractResult<Binary>> {
+        data.iter()
+            .map(|data| match data.strip_prefix("err:") {
+                Some(err) => ContractResult::Err(err.to_string()),
+                None => ContractResult::Ok(Binary::from(data.as_bytes())),
+            })
+            .collect()
+    }
+
+    fn aggregate(op: AggregateOp, results: &[ContractResult<Binary>]) -> AggregateResult {
+        let aggregation = Aggregation {
+            op,
+            pointer: "/amount".to_string(),
+        };
+        from_binary(&aggregation.apply(results).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn folds_numbers_and_reports_bad_inputs() {
+        let inputs = results(&[
+            r#"{"amount":"7"}"#,
+            r#"{"amount":2}"#,
+            "err:no such contract",
+            r#"{"amount":"12"}"#,
+            r#"{"balance":"1"}"#,
+            r#"{"amount":"3"}"#,
+        ]);
+        let sum = aggregate(AggregateOp::Sum, &inputs);
+        assert_eq!(sum.value, Some("24".to_string()));
+        assert_eq!(sum.inputs, 4);
+        assert_eq!(
+            sum.errors,
+            vec![
+                InputError {
+                    index: 2,
+                    error: "no such contract".to_string()
+                },
+                InputError {
+                    index: 4,
+                    error: "Path /amount not found in result".to_string()
+                },
+            ]
+        );
+        let value = |op, inputs| aggregate(op, inputs).value;
+        assert_eq!(value(AggregateOp::Min, &inputs).unwrap(), "2");
+        assert_eq!(value(AggregateOp::Max, &inputs).unwrap(), "12");
+        assert_eq!(value(AggregateOp::Count, &inputs).unwrap(), "4");
+        assert_eq!(value(AggregateOp::Median, &inputs).unwrap(), "5");
+        assert_eq!(value(AggregateOp::Median, &[]), None);
+
+        // one fraction turns the others into decimals
+        let inputs = results(&[r#"{"amount":"1.5"}"#, r#"{"amount":"2"}"#]);
+        assert_eq!(value(AggregateOp::Sum, &inputs).unwrap(), "3.5");
+        assert_eq!(value(AggregateOp::Median, &inputs).unwrap(), "1.75");
+
+        let max = format!(r#"{{"amount":"{}"}}"#, Uint128::MAX);
+        let aggregation = Aggregation {
+            op: AggregateOp::Sum,
+            pointer: "/amount".to_string(),
+        };
+        assert_eq!(
+            aggregation.apply(&results(&[&max, &max])),
+            ContractResult::Err("Overflow computing the sum of the results".to_string())
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use cosmwasm_std::{from_slice, to_vec, Binary, ContractResult, QueryRequest, WasmQuery};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::json::Json;
+
+/// Most queries a chained packet may hold, advertised by hosts in `AppVersion::max_chain_steps`
+pub const MAX_CHAIN_STEPS: u32 = 8;
+
+/// Replaces the placeholders of a query in a chain with values from earlier results.
+/// A placeholder is `${<index><pointer>}`, like `${0/voting_module}` for the field
+/// `voting_module` of the first result. It may appear in any string of the query, including
+/// the message of a smart query, and is replaced by a string, number or boolean.
+pub fn resolve_query<Q: Serialize + DeserializeOwned>(
+    query: &QueryRequest<Q>,
+    results: &[ContractResult<Binary>],
+) -> Result<QueryRequest<Q>, String> {
+    resolve_view_query(query, &[], results)
+}
+
+/// `resolve_query` for a chained view, filling in the requester's params in the same pass.
+/// Values are never scanned again, so a param can't smuggle in a placeholder of its own.
+pub fn resolve_view_query<Q: Serialize + DeserializeOwned>(
+    query: &QueryRequest<Q>,
+    params: &[(String, String)],
+    results: &[ContractResult<Binary>],
+) -> Result<QueryRequest<Q>, String> {
+    fill_placeholders(query, &|placeholder| {
+        // placeholders starting with a digit are always results, as in `fill_params`
+        if placeholder.starts_with(|c: char| c.is_ascii_digit()) {
+            return placeholder_value(placeholder, results);
+        }
+        match params.iter().find(|(name, _)| name == placeholder) {
+            Some((_, value)) => escape(value),
+            None => placeholder_value(placeholder, results),
+        }
+    })
+}
+
+/// Replaces the `${<name>}` placeholders of a view's query with the requester's params.
+/// Placeholders starting with a digit are left alone, chained views are filled in by
+/// `resolve_view_query` instead.
+pub fn fill_params<Q: Serialize + DeserializeOwned>(
+    query: &QueryRequest<Q>,
+    params: &[(String, String)],
+) -> Result<QueryRequest<Q>, String> {
+    fill_placeholders(query, &|placeholder| {
+        if placeholder.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(format!("${{{}}}", placeholder));
+        }
+        match params.iter().find(|(name, _)| name == placeholder) {
+            Some((_, value)) => escape(value),
+            None => Err(format!("Missing param {}", placeholder)),
+        }
+    })
+}
+
+type Lookup<'a> = &'a dyn Fn(&str) -> Result<String, String>;
+
+fn fill_placeholders<Q: Serialize + DeserializeOwned>(
+    query: &QueryRequest<Q>,
+    lookup: Lookup,
+) -> Result<QueryRequest<Q>, String> {
+    let text = to_vec(query).map_err(|err| err.to_string())?;
+    let text = substitute(&String::from_utf8_lossy(&text), lookup)?;
+    let mut query: QueryRequest<Q> = from_slice(text.as_bytes()).map_err(|err| err.to_string())?;
+    if let QueryRequest::Wasm(WasmQuery::Smart { msg, .. }) = &mut query {
+        if let Ok(text) = std::str::from_utf8(msg) {
+            *msg = substitute(text, lookup)?.into_bytes().into();
+        }
+    }
+    Ok(query)
+}
+
+fn substitute(text: &str, lookup: Lookup) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| "Unclosed placeholder".to_string())?
+            + start;
+        out.push_str(&lookup(&rest[start + 2..end])?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The value of a placeholder, escaped to sit inside a JSON string
+fn placeholder_value(
+    placeholder: &str,
+    results: &[ContractResult<Binary>],
+) -> Result<String, String> {
+    let (index, pointer) = placeholder.split_at(placeholder.find('/').unwrap_or(placeholder.len()));
+    let index: usize = index
+        .parse()
+        .map_err(|_| format!("Placeholder ${{{}}} needs a query index", placeholder))?;
+    let data = match results.get(index) {
+        Some(ContractResult::Ok(data)) => data,
+        Some(ContractResult::Err(_)) => return Err(format!("Query {} failed", index)),
+        None => return Err(format!("Query {} has not run yet", index)),
+    };
+    let json =
+        Json::from_slice(data).map_err(|_| format!("Result of query {} is not JSON", index))?;
+    match json.pointer(pointer) {
+        Some(Json::String(value)) => escape(value),
+        Some(Json::Number(value)) => Ok(value.to_string()),
+        Some(Json::Bool(value)) => Ok(value.to_string()),
+        Some(_) => Err(format!(
+            "${{{}}} is not a string, number or boolean",
+            placeholder
+        )),
+        None => Err(format!(
+            "Path {} not found in result of query {}",
+            pointer, index
+        )),
+    }
+}
+
+/// A string escaped to sit inside a JSON string
+fn escape(value: &str) -> Result<String, String> {
+    let quoted = to_vec(value).map_err(|err| err.to_string())?;
+    Ok(String::from_utf8_lossy(&quoted[1..quoted.len() - 1]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::Empty;
+
+    use super::*;
+
+    fn smart(contract_addr: &str, msg: &[u8]) -> QueryRequest<Empty> {
+        QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: contract_addr.to_string(),
+            msg: msg.into(),
+        })
+    }
+
+    #[test]
+    fn fills_in_earlier_results() {
+        let results = vec![ContractResult::Ok(Binary::from(
+            br#"{"voting_module":"juno1vote","height":12}"#,
+        ))];
+        let query = smart(
+            "${0/voting_module}",
+            br#"{"voting_power_at_height":{"address":"alice","height":${0/height}}}"#,
+        );
+        assert_eq!(
+            resolve_query(&query, &results),
+            Ok(smart(
+                "juno1vote",
+                br#"{"voting_power_at_height":{"address":"alice","height":12}}"#,
+            ))
+        );
+
+        let query = smart("${0/owner}", b"{}");
+        assert_eq!(
+            resolve_query(&query, &results),
+            Err("Path /owner not found in result of query 0".to_string())
+        );
+        let query = smart("${1/voting_module}", b"{}");
+        assert_eq!(
+            resolve_query(&query, &results),
+            Err("Query 1 has not run yet".to_string())
+        );
+    }
+
+    #[test]
+    fn fills_in_params() {
+        let query = smart(
+            "${dao}",
+            br#"{"member":{"addr":"${addr}","at":${0/height}}}"#,
+        );
+        let params = vec![
+            ("dao".to_string(), "juno1dao".to_string()),
+            ("addr".to_string(), "al\"ice".to_string()),
+        ];
+        assert_eq!(
+            fill_params(&query, &params),
+            Ok(smart(
+                "juno1dao",
+                br#"{"member":{"addr":"al\"ice","at":${0/height}}}"#
+            ))
+        );
+        assert_eq!(
+            fill_params(&query, &params[..1]),
+            Err("Missing param addr".to_string())
+        );
+    }
+
+    #[test]
+    fn params_cannot_inject_placeholders() {
+        let results = vec![ContractResult::Ok(Binary::from(
+            br#"{"secret":"hunter2","height":12}"#,
+        ))];
+        let query = smart(
+            "juno1dao",
+            br#"{"member":{"addr":"${addr}","at":${0/height}}}"#,
+        );
+        let params = vec![("addr".to_string(), "${0/secret}".to_string())];
+        assert_eq!(
+            resolve_view_query(&query, &params, &results),
+            Ok(smart(
+                "juno1dao",
+                br#"{"member":{"addr":"${0/secret}","at":12}}"#
+            ))
+        );
+
+        // nor can a param pose as an earlier result
+        let params = vec![
+            ("addr".to_string(), "alice".to_string()),
+            ("0/height".to_string(), "99".to_string()),
+        ];
+        assert_eq!(
+            resolve_view_query(&query, &params, &results),
+            Ok(smart("juno1dao", br#"{"member":{"addr":"alice","at":12}}"#))
+        );
+    }
+}
@@ -1,15 +1,58 @@
 pub use crate::{APP_ORDER, IBC_APP_VERSION};
-use cosmwasm_std::IbcOrder;
+use cosmwasm_std::{from_slice, IbcOrder};
 
 use thiserror::Error;
 
+use crate::icq::ICQ_VERSION;
+use crate::polytone::POLYTONE_VERSION;
+use crate::version::AppVersion;
+
 #[derive(Error, Debug, PartialEq)]
 pub enum SimpleIcaError {
     #[error("Only supports unordered channels")]
     InvalidChannelOrder,
 
-    #[error("Counterparty version must be '{0}'")]
+    #[error("Counterparty version must be '{0}' or JSON version metadata")]
     InvalidChannelVersion(&'static str),
+
+    #[error("Invalid version metadata: {0}")]
+    InvalidVersionMetadata(String),
+
+    #[error("No common packet encoding with counterparty")]
+    NoCommonEncoding,
+
+    #[error("Counterparty does not support {0} queries")]
+    UnsupportedQuery(String),
+
+    #[error("Counterparty accepts at most {0} queries per packet")]
+    BatchTooLarge(u32),
+
+    #[error("Counterparty does not apply projections")]
+    ProjectionsUnsupported,
+
+    #[error("Got {projections} projections for {queries} queries")]
+    ProjectionMismatch { queries: usize, projections: usize },
+
+    #[error("Invalid projection: {0}")]
+    InvalidProjection(String),
+
+    #[error("Counterparty does not run chained queries")]
+    ChainingUnsupported,
+
+    #[error("Counterparty runs at most {0} chained queries per packet")]
+    ChainTooLong(u32),
+
+    #[error("Counterparty does not aggregate results")]
+    AggregationUnsupported,
+
+    #[error("Invalid aggregation: {0}")]
+    InvalidAggregation(String),
+
+    #[error("Counterparty does not run views")]
+    ViewsUnsupported,
+
+    #[error("Counterparty does not forward requests to responders")]
+    RespondersUnsupported,
 }
 
 pub fn check_order(order: &IbcOrder) -> Result<(), SimpleIcaError> {
@@ -20,10 +63,22 @@ pub fn check_order(order: &IbcOrder) -> Result<(), SimpleIcaError> {
     }
 }
 
-pub fn check_version(version: &str) -> Result<(), SimpleIcaError> {
-    if version != IBC_APP_VERSION {
-        Err(SimpleIcaError::InvalidChannelVersion(IBC_APP_VERSION))
-    } else {
-        Ok(())
+/// Parses the JSON version metadata.
+/// The plain `simple-ica-v1` string of legacy counterparties, the async-icq `icq-1` and
+/// Polytone's `polytone-1` are accepted as well.
+pub fn check_version(version: &str) -> Result<AppVersion, SimpleIcaError> {
+    if version == IBC_APP_VERSION {
+        return Ok(AppVersion::legacy());
+    }
+    if version == ICQ_VERSION {
+        return Ok(AppVersion::icq());
+    }
+    if version == POLYTONE_VERSION {
+        return Ok(AppVersion::polytone());
+    }
+    if !version.starts_with('{') {
+        return Err(SimpleIcaError::InvalidChannelVersion(IBC_APP_VERSION));
     }
+    from_slice(version.as_bytes())
+        .map_err(|err| SimpleIcaError::InvalidVersionMetadata(err.to_string()))
 }
@@ -0,0 +1,466 @@
+use std::borrow::Cow;
+
+use cosmwasm_std::{from_slice, to_binary, to_vec, Binary, ContractResult, StdResult};
+use prost::Message;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::compression::Compression;
+use crate::ibc_msg::{
+    IbcQueryResponse, IbcQueryResponseV2, IbcQueryResult, PacketMsgV2, QueryAck, StdAck,
+};
+use crate::proto::{
+    decode, Acknowledgement, AcknowledgementResponse, ProtoQueryPacket, ProtoQueryResponse,
+    ProtoQueryResult,
+};
+use crate::version::{Encoding, ProtocolVersion};
+use crate::{icq, polytone};
+
+/// How packets and acks look on a channel, as negotiated in the handshake
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WireFormat {
+    pub version: ProtocolVersion,
+    /// Ignored for v1, which only speaks JSON
+    pub encoding: Encoding,
+    /// Ignored for v1, which is never compressed
+    pub compression: Option<Compression>,
+}
+
+impl From<ProtocolVersion> for WireFormat {
+    fn from(version: ProtocolVersion) -> Self {
+        WireFormat {
+            version,
+            encoding: Encoding::Json,
+            compression: None,
+        }
+    }
+}
+
+impl WireFormat {
+    fn is_proto(&self) -> bool {
+        self.version == ProtocolVersion::V2 && self.encoding == Encoding::Proto
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        match self.version {
+            ProtocolVersion::V2 => self.compression,
+            _ => None,
+        }
+    }
+
+    /// Compresses `data` if the channel allows it and it is worth it
+    fn compress(&self, data: Vec<u8>) -> Vec<u8> {
+        self.compression()
+            .and_then(|compression| compression.compress(&data))
+            .unwrap_or(data)
+    }
+
+    fn decompress<'a>(&self, data: &'a [u8]) -> StdResult<Cow<'a, [u8]>> {
+        match self.compression() {
+            Some(compression) => compression.decompress(data),
+            None => Ok(data.into()),
+        }
+    }
+
+    /// Serializes a packet, v1 has no room for the id and sender so they are dropped
+    pub fn encode_packet<Q: Serialize>(&self, packet: &PacketMsgV2<Q>) -> StdResult<Binary> {
+        match self.version {
+            ProtocolVersion::Icq => return icq::encode_packet(packet),
+            ProtocolVersion::Polytone => return polytone::encode_packet(packet),
+            _ => {}
+        }
+        let data = if self.is_proto() {
+            ProtoQueryPacket::from_packet(packet)?.encode_to_vec()
+        } else {
+            match self.version {
+                ProtocolVersion::V1 => to_vec(&packet.msg)?,
+                _ => to_vec(packet)?,
+            }
+        };
+        Ok(Binary(self.compress(data)))
+    }
+
+    /// Parses a packet, v1 packets get id 0 and an empty sender
+    pub fn decode_packet<Q: DeserializeOwned>(&self, data: &[u8]) -> StdResult<PacketMsgV2<Q>> {
+        match self.version {
+            ProtocolVersion::Icq => return icq::decode_packet(data),
+            ProtocolVersion::Polytone => return polytone::decode_packet(data),
+            _ => {}
+        }
+        let data = &*self.decompress(data)?;
+        if self.is_proto() {
+            return decode::<ProtoQueryPacket>(data)?.into_packet();
+        }
+        match self.version {
+            ProtocolVersion::V1 => Ok(PacketMsgV2 {
+                id: 0,
+                sender: String::new(),
+                msg: from_slice(data)?,
+            }),
+            _ => from_slice(data),
+        }
+    }
+
+    /// Serializes the successful ack for packet `id`
+    pub fn encode_ack(&self, id: u64, results: Vec<ContractResult<Binary>>) -> StdResult<Binary> {
+        match self.version {
+            ProtocolVersion::Icq => return icq::encode_ack(results),
+            ProtocolVersion::Polytone => return polytone::encode_ack(results),
+            _ => {}
+        }
+        if self.is_proto() {
+            let response = ProtoQueryResponse {
+                id,
+                results: results.into_iter().map(ProtoQueryResult::from).collect(),
+            };
+            return Ok(proto_ack(AcknowledgementResponse::Result(
+                self.compress(response.encode_to_vec()),
+            )));
+        }
+        match self.version {
+            ProtocolVersion::V1 => {
+                let results = results
+                    .iter()
+                    .map(to_binary)
+                    .collect::<StdResult<Vec<_>>>()?;
+                Ok(StdAck::success(&IbcQueryResponse { results }))
+            }
+            _ => {
+                let response = IbcQueryResponseV2 { id, results };
+                let compressed = match self.compression() {
+                    Some(compression) => compression.compress(&to_vec(&response)?),
+                    None => None,
+                };
+                let ack = match compressed {
+                    Some(compressed) => QueryAck::Compressed(Binary(compressed)),
+                    None => QueryAck::Result(response),
+                };
+                Ok(ack.ack())
+            }
+        }
+    }
+
+    /// Serializes an error ack. The message is prefixed with the protocol version so the
+    /// requester can tell which dialect the host thought it was speaking.
+    pub fn encode_error(&self, err: impl std::fmt::Display) -> Binary {
+        let err = format!("{}: {}", self.version.as_str(), err);
+        if self.version == ProtocolVersion::Polytone {
+            return polytone::encode_error(err);
+        }
+        if self.is_proto() {
+            return proto_ack(AcknowledgementResponse::Error(err));
+        }
+        StdAck::fail(err)
+    }
+
+    /// Parses an ack (successful or not) into the query results
+    pub fn decode_ack(&self, ack: &[u8]) -> StdResult<IbcQueryResult> {
+        match self.version {
+            ProtocolVersion::Icq => return icq::decode_ack(ack),
+            ProtocolVersion::Polytone => return polytone::decode_ack(ack),
+            _ => {}
+        }
+        if self.is_proto() {
+            let data = match decode::<Acknowledgement>(ack)?.response()? {
+                AcknowledgementResponse::Result(data) => data,
+                AcknowledgementResponse::Error(err) => return Ok(IbcQueryResult::Error(err)),
+            };
+            let results = decode::<ProtoQueryResponse>(&self.decompress(&data)?)?
+                .results
+                .into_iter()
+                .map(ProtoQueryResult::into_result)
+                .collect::<StdResult<_>>()?;
+            return Ok(IbcQueryResult::Ok(results));
+        }
+        let results = match self.version {
+            ProtocolVersion::V1 => {
+                let data = match from_slice(ack)? {
+                    StdAck::Result(data) => data,
+                    StdAck::Error(err) => return Ok(IbcQueryResult::Error(err)),
+                };
+                let response: IbcQueryResponse = from_slice(&data)?;
+                response
+                    .results
+                    .iter()
+                    .map(|res| from_slice(res))
+                    .collect::<StdResult<Vec<_>>>()?
+            }
+            _ => match from_slice(ack)? {
+                QueryAck::Result(response) => response.results,
+                QueryAck::Compressed(data) => {
+                    from_slice::<IbcQueryResponseV2>(&self.decompress(&data)?)?.results
+                }
+                QueryAck::Error(err) => return Ok(IbcQueryResult::Error(err)),
+            },
+        };
+        Ok(IbcQueryResult::Ok(results))
+    }
+}
+
+fn proto_ack(response: AcknowledgementResponse) -> Binary {
+    Binary(
+        Acknowledgement {
+            response: Some(response),
+        }
+        .encode_to_vec(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{AllBalanceResponse, BankQuery, Coin, Empty, QueryRequest};
+
+    use crate::{PacketMsg, Projection};
+
+    use super::*;
+
+    const V1: WireFormat = WireFormat {
+        version: ProtocolVersion::V1,
+        encoding: Encoding::Json,
+        compression: None,
+    };
+    const V2: WireFormat = WireFormat {
+        version: ProtocolVersion::V2,
+        encoding: Encoding::Json,
+        compression: None,
+    };
+    const V2_PROTO: WireFormat = WireFormat {
+        version: ProtocolVersion::V2,
+        encoding: Encoding::Proto,
+        compression: None,
+    };
+
+    fn packet() -> PacketMsgV2 {
+        PacketMsgV2 {
+            id: 7,
+            sender: "sender".to_string(),
+            msg: PacketMsg::IbcQuery {
+                msgs: vec![
+                    QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
+                        address: "foo".to_string(),
+                    }),
+                    crate::denom_trace_query("ABCD"),
+                ],
+                callback: "callback".to_string(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        }
+    }
+
+    #[test]
+    fn v1_packets_stay_compatible() {
+        let packet = packet();
+        let data = V1.encode_packet(&packet).unwrap();
+        // legacy counterparties parse a plain PacketMsg
+        let legacy: PacketMsg = from_slice(&data).unwrap();
+        assert_eq!(legacy, packet.msg);
+
+        let decoded: PacketMsgV2 = V1.decode_packet(&data).unwrap();
+        assert_eq!(decoded.id, 0);
+        assert_eq!(decoded.msg, packet.msg);
+
+        // v1 never speaks protobuf, whatever the metadata says
+        let v1_proto = WireFormat {
+            encoding: Encoding::Proto,
+            ..V1
+        };
+        assert_eq!(v1_proto.encode_packet(&packet).unwrap(), data);
+    }
+
+    #[test]
+    fn v2_packets_roundtrip() {
+        let view = PacketMsgV2 {
+            msg: PacketMsg::View {
+                name: "voting_power".to_string(),
+                params: vec![("addr".to_string(), "juno1alice".to_string())],
+                callback: "callback".to_string(),
+            },
+            ..packet()
+        };
+        let responder = PacketMsgV2 {
+            msg: PacketMsg::Responder {
+                name: "risk".to_string(),
+                request: Binary::from(br#"{"score":{"address":"juno1alice"}}"#),
+                callback: "callback".to_string(),
+            },
+            ..packet()
+        };
+        let mut projected = packet();
+        if let PacketMsg::IbcQuery { projections, .. } = &mut projected.msg {
+            *projections = vec![None, Some(Projection::Pointer("/path".to_string()))];
+        }
+        for packet in [packet(), projected, view, responder] {
+            for format in [V2, V2_PROTO] {
+                let data = format.encode_packet(&packet).unwrap();
+                let decoded: PacketMsgV2 = format.decode_packet(&data).unwrap();
+                assert_eq!(decoded, packet);
+            }
+        }
+    }
+
+    #[test]
+    fn proto_matches_json() {
+        let packet = packet();
+        let json: PacketMsgV2 = V2
+            .decode_packet(&V2.encode_packet(&packet).unwrap())
+            .unwrap();
+        let proto: PacketMsgV2 = V2_PROTO
+            .decode_packet(&V2_PROTO.encode_packet(&packet).unwrap())
+            .unwrap();
+        assert_eq!(json, proto);
+        // the formats are not interchangeable
+        V2.decode_packet::<Empty>(&V2_PROTO.encode_packet(&packet).unwrap())
+            .unwrap_err();
+
+        let results = vec![
+            ContractResult::Ok(Binary::from(b"{}")),
+            ContractResult::Err("not found".to_string()),
+        ];
+        assert_eq!(
+            V2.decode_ack(&V2.encode_ack(7, results.clone()).unwrap())
+                .unwrap(),
+            V2_PROTO
+                .decode_ack(&V2_PROTO.encode_ack(7, results).unwrap())
+                .unwrap(),
+        );
+        assert_eq!(
+            V2.decode_ack(&V2.encode_error("boom")).unwrap(),
+            V2_PROTO.decode_ack(&V2_PROTO.encode_error("boom")).unwrap(),
+        );
+    }
+
+    #[test]
+    fn proto_error_ack_is_standard() {
+        let ack = V2_PROTO.encode_error("boom");
+        let ack: Acknowledgement = decode(&ack).unwrap();
+        assert_eq!(
+            ack.response,
+            Some(AcknowledgementResponse::Error(
+                "simple-ica-v2: boom".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn acks_roundtrip() {
+        let results = vec![
+            ContractResult::Ok(Binary::from(b"{}")),
+            ContractResult::Err("not found".to_string()),
+        ];
+        for format in [V1, V2, V2_PROTO] {
+            let ack = format.encode_ack(7, results.clone()).unwrap();
+            assert_eq!(
+                format.decode_ack(&ack).unwrap(),
+                IbcQueryResult::Ok(results.clone())
+            );
+        }
+        for format in [V1, V2] {
+            let ack = StdAck::fail("boom".to_string());
+            assert_eq!(
+                format.decode_ack(&ack).unwrap(),
+                IbcQueryResult::Error("boom".to_string())
+            );
+        }
+    }
+
+    /// A typical bank balance answer, as the host's querier returns it
+    fn balance_result(coins: usize) -> ContractResult<Binary> {
+        let amount = (0..coins)
+            .map(|i| Coin::new(1_000_000 + i as u128, format!("ibc/{:064X}", i)))
+            .collect();
+        ContractResult::Ok(to_binary(&AllBalanceResponse { amount }).unwrap())
+    }
+
+    #[test]
+    fn v2_acks_embed_results_once() {
+        for (queries, coins) in [(1, 1), (5, 3), (50, 10)] {
+            let results = vec![balance_result(coins); queries];
+            let raw: usize = results.iter().map(|r| r.clone().unwrap().len()).sum();
+
+            let v1 = V1.encode_ack(7, results.clone()).unwrap();
+            // what v2 acks looked like when they went through `StdAck::success`
+            let nested = StdAck::success(&IbcQueryResponseV2 {
+                id: 7,
+                results: results.clone(),
+            });
+            let v2 = V2.encode_ack(7, results.clone()).unwrap();
+            let v2_proto = V2_PROTO.encode_ack(7, results).unwrap();
+
+            // base64 once is 4/3 of the data, double encoding gets close to 16/9 and more
+            assert!(v1.len() * 3 > raw * 5, "v1 {} raw {}", v1.len(), raw);
+            assert!(
+                nested.len() * 9 > raw * 16,
+                "nested {} raw {}",
+                nested.len(),
+                raw
+            );
+            let overhead = 64 + 16 * queries;
+            assert!(
+                v2.len() <= raw * 4 / 3 + overhead,
+                "v2 {} raw {}",
+                v2.len(),
+                raw
+            );
+            // and protobuf doesn't need base64 at all
+            assert!(
+                v2_proto.len() <= raw + overhead,
+                "proto {} raw {}",
+                v2_proto.len(),
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn compressed_results_are_identical() {
+        let results = vec![balance_result(10); 20];
+        let mut packet = packet();
+        let PacketMsg::IbcQuery { msgs, .. } = &mut packet.msg else {
+            unreachable!()
+        };
+        *msgs = vec![msgs[0].clone(); 50];
+
+        for plain in [V2, V2_PROTO] {
+            let compressed = WireFormat {
+                compression: Some(Compression::Deflate),
+                ..plain
+            };
+
+            let data = compressed.encode_packet(&packet).unwrap();
+            assert!(data.len() < plain.encode_packet(&packet).unwrap().len());
+            let decoded: PacketMsgV2 = compressed.decode_packet(&data).unwrap();
+            assert_eq!(decoded, packet);
+
+            let ack = compressed.encode_ack(7, results.clone()).unwrap();
+            let plain_ack = plain.encode_ack(7, results.clone()).unwrap();
+            assert!(
+                ack.len() * 4 < plain_ack.len(),
+                "{} {}",
+                ack.len(),
+                plain_ack.len()
+            );
+            assert_eq!(
+                compressed.decode_ack(&ack).unwrap(),
+                plain.decode_ack(&plain_ack).unwrap()
+            );
+            // small acks are not worth it
+            let small = vec![ContractResult::Ok(Binary::from(b"{}"))];
+            assert_eq!(
+                compressed.encode_ack(7, small.clone()).unwrap(),
+                plain.encode_ack(7, small).unwrap()
+            );
+        }
+
+        // v1 ignores the setting
+        let v1 = WireFormat {
+            compression: Some(Compression::Deflate),
+            ..V1
+        };
+        assert_eq!(
+            v1.encode_packet(&packet).unwrap(),
+            V1.encode_packet(&packet).unwrap()
+        );
+    }
+}
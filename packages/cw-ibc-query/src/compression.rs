@@ -0,0 +1,94 @@
+use cosmwasm_std::{StdError, StdResult};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Payloads at or below this size are always sent as they are
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+/// Compressed payloads may not inflate to more than this
+pub const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
+/// Marks a compressed payload. Neither JSON nor protobuf can start with a zero byte.
+const MAGIC: &[u8] = b"\0dfl";
+const LEVEL: u8 = 6;
+
+/// Compression codecs for packet data and ack results
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Raw deflate, as produced by `miniz_oxide` at level 6
+    Deflate,
+}
+
+impl Compression {
+    /// Compresses `data` if it is above the threshold and actually gets smaller
+    pub fn compress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() <= COMPRESSION_THRESHOLD {
+            return None;
+        }
+        let mut compressed = MAGIC.to_vec();
+        match self {
+            Compression::Deflate => compressed.extend(compress_to_vec(data, LEVEL)),
+        }
+        (compressed.len() < data.len()).then_some(compressed)
+    }
+
+    /// Inverse of `compress`, data without the marker is returned as it is
+    pub fn decompress<'a>(&self, data: &'a [u8]) -> StdResult<std::borrow::Cow<'a, [u8]>> {
+        let compressed = match data.strip_prefix(MAGIC) {
+            Some(compressed) => compressed,
+            None => return Ok(data.into()),
+        };
+        match self {
+            Compression::Deflate => decompress_to_vec_with_limit(compressed, MAX_DECOMPRESSED_SIZE)
+                .map(Into::into)
+                .map_err(|err| StdError::generic_err(format!("Invalid compressed data: {}", err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_only_large_payloads() {
+        let small = b"{\"bank\":{}}".to_vec();
+        assert_eq!(Compression::Deflate.compress(&small), None);
+        assert_eq!(Compression::Deflate.decompress(&small).unwrap(), small);
+
+        let large = b"{\"denom\":\"uatom\",\"amount\":\"1\"}".repeat(100);
+        let compressed = Compression::Deflate.compress(&large).unwrap();
+        assert!(compressed.len() < large.len() / 10);
+        // deterministic, every validator must produce the same ack
+        assert_eq!(Compression::Deflate.compress(&large).unwrap(), compressed);
+        assert_eq!(Compression::Deflate.decompress(&compressed).unwrap(), large);
+
+        // incompressible data is left alone
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..2048)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(Compression::Deflate.compress(&noise), None);
+    }
+
+    #[test]
+    fn refuses_bombs_and_garbage() {
+        let bomb = Compression::Deflate
+            .compress(&vec![0u8; MAX_DECOMPRESSED_SIZE + 1])
+            .unwrap();
+        Compression::Deflate.decompress(&bomb).unwrap_err();
+
+        let mut garbage = MAGIC.to_vec();
+        garbage.extend([0xff; 16]);
+        Compression::Deflate.decompress(&garbage).unwrap_err();
+    }
+}
@@ -1,19 +1,71 @@
 use cosmwasm_std::{
-    from_slice, to_binary, Binary, CosmosMsg, Empty, IbcPacketAckMsg, QueryRequest, StdResult,
-    WasmMsg,
+    from_slice, to_binary, Binary, ContractResult, CosmosMsg, Empty, IbcPacketAckMsg, QueryRequest,
+    StdResult, WasmMsg,
 };
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-/// This is the message we send over the IBC channel
+use crate::aggregate::Aggregation;
+use crate::projection::Projection;
+use crate::quorum::QuorumResult;
+
+/// This is the message we send over the IBC channel.
+/// `Q` is the chain-specific custom query type, both sides must agree on it
+/// (see `AppVersion::custom_query`).
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum PacketMsg {
+pub enum PacketMsg<Q = Empty> {
     IbcQuery {
-        msgs: Vec<QueryRequest<Empty>>,
+        msgs: Vec<QueryRequest<Q>>,
+        callback: String,
+        /// What the host should keep of each query's result, one per query or none at all
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        projections: Vec<Option<Projection>>,
+        /// Whether queries may use earlier results through placeholders, see `resolve_query`
+        #[serde(default, skip_serializing_if = "is_false")]
+        chained: bool,
+        /// Folds all results into one `AggregateResult` on the host, after projections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        aggregation: Option<Aggregation>,
+    },
+    /// Runs the queries the host registered under `name`, see `fill_params`
+    View {
+        name: String,
+        /// Values for the view's `${<name>}` placeholders
+        params: Vec<(String, String)>,
         callback: String,
     },
+    /// Asks the contract the host registered as responder `name`, see `ResponderQueryMsg`
+    Responder {
+        name: String,
+        request: Binary,
+        callback: String,
+    },
+}
+
+impl<Q> PacketMsg<Q> {
+    pub fn callback(&self) -> &str {
+        match self {
+            PacketMsg::IbcQuery { callback, .. }
+            | PacketMsg::View { callback, .. }
+            | PacketMsg::Responder { callback, .. } => callback,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// The v2 packet, a `PacketMsg` plus the metadata v1 lacked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PacketMsgV2<Q = Empty> {
+    /// Assigned by the requester, echoed back in the ack
+    pub id: u64,
+    /// Who asked for the queries on the requesting chain
+    pub sender: String,
+    pub msg: PacketMsg<Q>,
 }
 
 /// This is a generic ICS acknowledgement format.
@@ -61,6 +113,32 @@ impl StdAck {
     }
 }
 
+/// The v2 ack. Same shape as `StdAck`, but a successful ack embeds the response directly
+/// instead of JSON encoding it into another base64 blob, so each result is encoded only once.
+/// Error acks are identical to `StdAck::Error`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryAck {
+    Result(IbcQueryResponseV2),
+    /// A compressed `IbcQueryResponseV2`, on channels that negotiated compression
+    Compressed(Binary),
+    Error(String),
+}
+
+impl QueryAck {
+    pub fn ack(&self) -> Binary {
+        to_binary(self).unwrap()
+    }
+
+    pub fn unwrap(self) -> IbcQueryResponseV2 {
+        match self {
+            QueryAck::Result(response) => response,
+            QueryAck::Compressed(_) => panic!("compressed, use WireFormat::decode_ack"),
+            QueryAck::Error(err) => panic!("{}", err),
+        }
+    }
+}
+
 /// ReceiveIbcResponseMsg should be de/serialized under `Receive()` variant in a ExecuteMsg
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -90,12 +168,123 @@ impl ReceiveIbcResponseMsg {
     }
 }
 
+/// ReceiveIbcQueryResponseMsg is the callback for queries sent on v2 channels.
+/// Unlike `ReceiveIbcResponseMsg` it carries the decoded results, so receivers don't need to
+/// know how the ack was encoded. It should be de/serialized under `ReceiveIbcQueryResponse()`
+/// variant in a ExecuteMsg
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ReceiveIbcQueryResponseMsg {
+    /// Local channel the queries were sent on
+    pub channel_id: String,
+    /// Id assigned when the queries were sent
+    pub query_id: u64,
+    pub result: IbcQueryResult,
+}
+
+impl ReceiveIbcQueryResponseMsg {
+    /// serializes the message
+    pub fn into_binary(self) -> StdResult<Binary> {
+        let msg = ReceiverExecuteMsg::ReceiveIbcQueryResponse(self);
+        to_binary(&msg)
+    }
+
+    /// creates a cosmos_msg sending this struct to the named contract
+    pub fn into_cosmos_msg<T: Into<String>, C>(self, contract_addr: T) -> StdResult<CosmosMsg<C>>
+    where
+        C: Clone + std::fmt::Debug + PartialEq + JsonSchema,
+    {
+        let msg = self.into_binary()?;
+        let execute = WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg,
+            funds: vec![],
+        };
+        Ok(execute.into())
+    }
+}
+
+/// ReceiveIbcQueryGroupResponseMsg is the callback for queries sent to several channels at once.
+/// It should be de/serialized under `ReceiveIbcQueryGroupResponse()` variant in a ExecuteMsg
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ReceiveIbcQueryGroupResponseMsg {
+    /// Id assigned when the group was sent
+    pub group_id: u64,
+    /// How the queries ended on each channel that answered in time, sorted by channel
+    pub results: Vec<ChannelQueryResult>,
+    /// Channels still without an answer when the deadline passed. Their results follow as
+    /// `ReceiveIbcQueryResponse` callbacks.
+    pub pending: Vec<String>,
+    /// How the channels voted, for groups sent with a quorum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quorum: Option<QuorumResult>,
+}
+
+/// One channel's part of a `ReceiveIbcQueryGroupResponseMsg`
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct ChannelQueryResult {
+    pub channel_id: String,
+    pub result: IbcQueryResult,
+}
+
+impl ReceiveIbcQueryGroupResponseMsg {
+    /// serializes the message
+    pub fn into_binary(self) -> StdResult<Binary> {
+        let msg = ReceiverExecuteMsg::ReceiveIbcQueryGroupResponse(self);
+        to_binary(&msg)
+    }
+
+    /// creates a cosmos_msg sending this struct to the named contract
+    pub fn into_cosmos_msg<T: Into<String>, C>(self, contract_addr: T) -> StdResult<CosmosMsg<C>>
+    where
+        C: Clone + std::fmt::Debug + PartialEq + JsonSchema,
+    {
+        let msg = self.into_binary()?;
+        let execute = WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg,
+            funds: vec![],
+        };
+        Ok(execute.into())
+    }
+}
+
+/// How a batch of queries ended
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum IbcQueryResult {
+    /// One result per query, in the order they were sent
+    Ok(Vec<ContractResult<Binary>>),
+    /// The host failed the whole packet
+    Error(String),
+    /// The packet was never received
+    Timeout {},
+}
+
 /// This is just a helper to properly serialize the above message.
 /// The actual receiver should include this variant in the larger ExecuteMsg enum
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum ReceiverExecuteMsg {
     ReceiveIbcResponse(ReceiveIbcResponseMsg),
+    ReceiveIbcQueryResponse(ReceiveIbcQueryResponseMsg),
+    ReceiveIbcQueryGroupResponse(ReceiveIbcQueryGroupResponseMsg),
+}
+
+/// The smart query responder contracts answer. Their answer is the only result of the
+/// `PacketMsg::Responder` packet, a failed query fails that result.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponderQueryMsg {
+    IbcRequest(IbcRequestMsg),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct IbcRequestMsg {
+    /// Our end of the channel the request arrived on
+    pub channel_id: String,
+    /// Who sent it on the requesting chain
+    pub sender: String,
+    pub request: Binary,
 }
 
 /// Return the data field for each message
@@ -103,3 +292,12 @@ pub enum ReceiverExecuteMsg {
 pub struct IbcQueryResponse {
     pub results: Vec<Binary>,
 }
+
+/// The v2 ack payload, results are embedded directly instead of JSON encoded one by one.
+/// Sent as `QueryAck::Result`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IbcQueryResponseV2 {
+    /// Id of the packet this answers
+    pub id: u64,
+    pub results: Vec<ContractResult<Binary>>,
+}
@@ -0,0 +1,302 @@
+use cosmwasm_std::{
+    from_slice, to_binary, to_vec, Binary, ContractResult, QueryRequest, StdError, StdResult,
+};
+use prost::Message;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::ibc_msg::{IbcQueryResult, PacketMsg, PacketMsgV2, StdAck};
+use crate::proto::decode;
+
+/// Channel version of the async-icq (interchain queries) app
+pub const ICQ_VERSION: &str = "icq-1";
+/// Port the native async-icq host module binds to
+pub const ICQ_HOST_PORT: &str = "icqhost";
+
+/// icq.v1.InterchainQueryPacketData, sent as proto JSON
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InterchainQueryPacketData {
+    /// A protobuf encoded `CosmosQuery`
+    pub data: Binary,
+    #[serde(default)]
+    pub memo: String,
+}
+
+/// icq.v1.InterchainQueryPacketAck, the proto JSON in the result of a successful ack
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InterchainQueryPacketAck {
+    /// A protobuf encoded `CosmosResponse`
+    pub data: Binary,
+}
+
+/// What we put in the memo of outgoing packets, so acks and timeouts can find the callback.
+/// Hosts ignore the memo.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct IcqMemo {
+    pub id: u64,
+    pub sender: String,
+    pub callback: String,
+}
+
+/// icq.v1.CosmosQuery
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CosmosQuery {
+    #[prost(message, repeated, tag = "1")]
+    pub requests: Vec<RequestQuery>,
+}
+
+/// tendermint.abci.RequestQuery
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RequestQuery {
+    #[prost(bytes, tag = "1")]
+    pub data: Vec<u8>,
+    #[prost(string, tag = "2")]
+    pub path: String,
+    #[prost(int64, tag = "3")]
+    pub height: i64,
+    #[prost(bool, tag = "4")]
+    pub prove: bool,
+}
+
+/// icq.v1.CosmosResponse
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CosmosResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub responses: Vec<ResponseQuery>,
+}
+
+/// tendermint.abci.ResponseQuery, without the proof we never ask for
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResponseQuery {
+    #[prost(uint32, tag = "1")]
+    pub code: u32,
+    #[prost(string, tag = "3")]
+    pub log: String,
+    #[prost(string, tag = "4")]
+    pub info: String,
+    #[prost(int64, tag = "5")]
+    pub index: i64,
+    #[prost(bytes, tag = "6")]
+    pub key: Vec<u8>,
+    #[prost(bytes, tag = "7")]
+    pub value: Vec<u8>,
+    #[prost(int64, tag = "9")]
+    pub height: i64,
+    #[prost(string, tag = "10")]
+    pub codespace: String,
+}
+
+/// async-icq only carries ABCI queries, i.e. our Stargate queries
+pub(crate) fn encode_packet<Q: Serialize>(packet: &PacketMsgV2<Q>) -> StdResult<Binary> {
+    let (msgs, callback) = match &packet.msg {
+        PacketMsg::IbcQuery { msgs, callback, .. } => (msgs, callback),
+        _ => {
+            return Err(StdError::generic_err(
+                "async-icq packets only carry queries",
+            ))
+        }
+    };
+    let requests = msgs
+        .iter()
+        .map(|query| match query {
+            QueryRequest::Stargate { path, data } => Ok(RequestQuery {
+                data: data.to_vec(),
+                path: path.clone(),
+                height: 0,
+                prove: false,
+            }),
+            _ => Err(StdError::generic_err(format!(
+                "{} only carries Stargate queries",
+                ICQ_VERSION
+            ))),
+        })
+        .collect::<StdResult<_>>()?;
+    let memo = IcqMemo {
+        id: packet.id,
+        sender: packet.sender.clone(),
+        callback: callback.clone(),
+    };
+    to_binary(&InterchainQueryPacketData {
+        data: Binary(CosmosQuery { requests }.encode_to_vec()),
+        memo: String::from_utf8(to_vec(&memo)?)?,
+    })
+}
+
+/// Packets from native controllers have no memo of ours, they get id 0 and no callback
+pub(crate) fn decode_packet<Q: DeserializeOwned>(data: &[u8]) -> StdResult<PacketMsgV2<Q>> {
+    let packet: InterchainQueryPacketData = from_slice(data)?;
+    let query: CosmosQuery = decode(&packet.data)?;
+    // contracts can only see the current state, and without proofs
+    if query
+        .requests
+        .iter()
+        .any(|req| req.height != 0 || req.prove)
+    {
+        return Err(StdError::generic_err(
+            "Only queries for the latest height without proof are supported",
+        ));
+    }
+    let memo = from_slice(packet.memo.as_bytes()).unwrap_or(IcqMemo {
+        id: 0,
+        sender: String::new(),
+        callback: String::new(),
+    });
+    Ok(PacketMsgV2 {
+        id: memo.id,
+        sender: memo.sender,
+        msg: PacketMsg::IbcQuery {
+            msgs: query
+                .requests
+                .into_iter()
+                .map(|request| QueryRequest::Stargate {
+                    path: request.path,
+                    data: Binary(request.data),
+                })
+                .collect(),
+            callback: memo.callback,
+            projections: vec![],
+            chained: false,
+            aggregation: None,
+        },
+    })
+}
+
+pub(crate) fn encode_ack(results: Vec<ContractResult<Binary>>) -> StdResult<Binary> {
+    let responses = results
+        .into_iter()
+        .map(|result| match result {
+            ContractResult::Ok(value) => ResponseQuery {
+                value: value.0,
+                ..ResponseQuery::default()
+            },
+            ContractResult::Err(log) => ResponseQuery {
+                code: 1,
+                log,
+                ..ResponseQuery::default()
+            },
+        })
+        .collect();
+    Ok(StdAck::success(&InterchainQueryPacketAck {
+        data: Binary(CosmosResponse { responses }.encode_to_vec()),
+    }))
+}
+
+pub(crate) fn decode_ack(ack: &[u8]) -> StdResult<IbcQueryResult> {
+    let data = match from_slice(ack)? {
+        StdAck::Result(data) => data,
+        StdAck::Error(err) => return Ok(IbcQueryResult::Error(err)),
+    };
+    let ack: InterchainQueryPacketAck = from_slice(&data)?;
+    let response: CosmosResponse = decode(&ack.data)?;
+    Ok(IbcQueryResult::Ok(
+        response
+            .responses
+            .into_iter()
+            .map(|res| match res.code {
+                0 => ContractResult::Ok(Binary(res.value)),
+                code => ContractResult::Err(format!("{} (code {})", res.log, code)),
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{BankQuery, Empty};
+
+    use super::*;
+    use crate::denom_trace_query;
+
+    fn packet(msgs: Vec<QueryRequest<Empty>>) -> PacketMsgV2 {
+        PacketMsgV2 {
+            id: 4,
+            sender: "sender".to_string(),
+            msg: PacketMsg::IbcQuery {
+                msgs,
+                callback: "callback".to_string(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        }
+    }
+
+    #[test]
+    fn packets_carry_abci_queries() {
+        let packet = packet(vec![denom_trace_query("ABCD")]);
+        let data = encode_packet(&packet).unwrap();
+
+        // this is what the host module sees
+        let raw: InterchainQueryPacketData = from_slice(&data).unwrap();
+        let query: CosmosQuery = decode(&raw.data).unwrap();
+        assert_eq!(query.requests.len(), 1);
+        assert_eq!(query.requests[0].path, crate::DENOM_TRACE_PATH);
+        assert_eq!(query.requests[0].height, 0);
+        assert!(!query.requests[0].prove);
+
+        // and we get our callback back from the memo
+        assert_eq!(decode_packet::<Empty>(&data).unwrap(), packet);
+
+        let bank = QueryRequest::Bank(BankQuery::AllBalances {
+            address: "foo".to_string(),
+        });
+        encode_packet(&self::packet(vec![bank])).unwrap_err();
+
+        // we can't answer historical queries
+        let query = CosmosQuery {
+            requests: vec![RequestQuery {
+                path: crate::DENOM_TRACE_PATH.to_string(),
+                height: 12,
+                ..RequestQuery::default()
+            }],
+        };
+        let data = to_binary(&InterchainQueryPacketData {
+            data: Binary(query.encode_to_vec()),
+            memo: String::new(),
+        })
+        .unwrap();
+        decode_packet::<Empty>(&data).unwrap_err();
+    }
+
+    #[test]
+    fn decodes_host_acks() {
+        // built the way the host module does
+        let response = CosmosResponse {
+            responses: vec![
+                ResponseQuery {
+                    value: b"balance".to_vec(),
+                    ..ResponseQuery::default()
+                },
+                ResponseQuery {
+                    code: 18,
+                    log: "not found".to_string(),
+                    ..ResponseQuery::default()
+                },
+            ],
+        };
+        let packet_ack = format!(
+            r#"{{"data":"{}"}}"#,
+            Binary(response.encode_to_vec()).to_base64()
+        );
+        let ack = format!(
+            r#"{{"result":"{}"}}"#,
+            Binary(packet_ack.into_bytes()).to_base64()
+        );
+        let expected = IbcQueryResult::Ok(vec![
+            ContractResult::Ok(Binary::from(b"balance")),
+            ContractResult::Err("not found (code 18)".to_string()),
+        ]);
+        assert_eq!(decode_ack(ack.as_bytes()).unwrap(), expected);
+
+        let ack = br#"{"error":"ABCI code: 1: error handling packet: see events for details"}"#;
+        assert!(matches!(decode_ack(ack).unwrap(), IbcQueryResult::Error(_)));
+
+        // our own encoding gives the same results
+        let results = vec![ContractResult::Ok(Binary::from(b"balance"))];
+        assert_eq!(
+            decode_ack(&encode_ack(results.clone()).unwrap()).unwrap(),
+            IbcQueryResult::Ok(results)
+        );
+    }
+}
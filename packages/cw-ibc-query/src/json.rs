@@ -0,0 +1,198 @@
+use std::fmt;
+
+use cosmwasm_std::{from_slice, to_vec, StdError, StdResult};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+
+/// Any JSON document. Numbers are integers only, as contracts don't do floats.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i128),
+    String(String),
+    Array(Vec<Json>),
+    /// Fields in the order they were written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn from_slice(data: &[u8]) -> StdResult<Self> {
+        from_slice(data)
+    }
+
+    /// Writes the document as compact JSON
+    pub fn to_vec(&self) -> StdResult<Vec<u8>> {
+        let mut out = vec![];
+        self.write(&mut out)?;
+        Ok(out)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> StdResult<()> {
+        match self {
+            Json::Null => out.extend_from_slice(b"null"),
+            Json::Bool(value) => out.extend_from_slice(value.to_string().as_bytes()),
+            Json::Number(value) => out.extend_from_slice(value.to_string().as_bytes()),
+            Json::String(value) => out.extend(to_vec(value)?),
+            Json::Array(items) => {
+                out.push(b'[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    item.write(out)?;
+                }
+                out.push(b']');
+            }
+            Json::Object(fields) => {
+                out.push(b'{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    out.extend(to_vec(key)?);
+                    out.push(b':');
+                    value.write(out)?;
+                }
+                out.push(b'}');
+            }
+        }
+        Ok(())
+    }
+
+    /// The field `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The value at a JSON pointer like `/balance/amount`, see RFC 6901
+    pub fn pointer(&self, pointer: &str) -> Option<&Json> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        let mut tokens = pointer.strip_prefix('/')?.split('/');
+        tokens.try_fold(self, |json, token| {
+            let token = token.replace("~1", "/").replace("~0", "~");
+            match json {
+                Json::Object(_) => json.get(&token),
+                Json::Array(items) => token.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Sets the field `key` of an object, replacing what was there
+    pub fn insert(&mut self, key: &str, value: Json) -> StdResult<()> {
+        let fields = match self {
+            Json::Object(fields) => fields,
+            _ => return Err(StdError::generic_err("Not a JSON object")),
+        };
+        match fields.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value,
+            None => fields.push((key.to_string(), value)),
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Json, E> {
+        Ok(Json::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Json, E> {
+        Ok(Json::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Json, E> {
+        Ok(Json::Number(value.into()))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Json, E> {
+        Ok(Json::Number(value.into()))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Json, E> {
+        Ok(Json::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Json, E> {
+        Ok(Json::String(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Json::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut fields = vec![];
+        while let Some((Key(key), value)) = map.next_entry()? {
+            fields.push((key, value));
+        }
+        Ok(Json::Object(fields))
+    }
+}
+
+/// An object key, serde-json-wasm only reads those as `str`
+struct Key(String);
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.deserialize_str(JsonVisitor)? {
+            Json::String(key) => Ok(Key(key)),
+            _ => Err(de::Error::custom("Object keys must be strings")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let data =
+            br#"{"accounts":["a\"b",null,true],"total":-12,"next":{"id":18446744073709551615}}"#;
+        let json = Json::from_slice(data).unwrap();
+        assert_eq!(json.get("total"), Some(&Json::Number(-12)),);
+        assert_eq!(json.to_vec().unwrap(), data.to_vec());
+
+        let mut json = Json::from_slice(br#" { "limit" : 30 } "#).unwrap();
+        json.insert("start_after", Json::String("x".to_string()))
+            .unwrap();
+        json.insert("limit", Json::Number(10)).unwrap();
+        assert_eq!(
+            json.to_vec().unwrap(),
+            br#"{"limit":10,"start_after":"x"}"#.to_vec()
+        );
+
+        Json::from_slice(b"1.5").unwrap_err();
+
+        let json = Json::from_slice(br#"{"a/b":[{"c":1}],"d~":2}"#).unwrap();
+        assert_eq!(json.pointer("/a~1b/0/c"), Some(&Json::Number(1)));
+        assert_eq!(json.pointer("/d~0"), Some(&Json::Number(2)));
+        assert_eq!(json.pointer(""), Some(&json));
+        assert_eq!(json.pointer("/a~1b/1"), None);
+        assert_eq!(json.pointer("a~1b"), None);
+    }
+}
@@ -1,14 +1,64 @@
+mod aggregate;
+mod chain;
 mod checks;
+mod codec;
+mod compression;
 mod ibc_msg;
+mod icq;
+mod json;
+mod pagination;
+mod polytone;
+mod projection;
+mod proto;
+mod quorum;
+mod stargate;
+mod version;
 
 use cosmwasm_std::IbcOrder;
 
+pub use crate::aggregate::{AggregateOp, AggregateResult, Aggregation, InputError};
+pub use crate::chain::{fill_params, resolve_query, resolve_view_query, MAX_CHAIN_STEPS};
 pub use crate::checks::{check_order, check_version, SimpleIcaError};
+pub use crate::codec::WireFormat;
+pub use crate::compression::{Compression, COMPRESSION_THRESHOLD, MAX_DECOMPRESSED_SIZE};
 pub use crate::ibc_msg::{
-    IbcQueryResponse, PacketMsg, ReceiveIbcResponseMsg, ReceiverExecuteMsg, StdAck,
+    ChannelQueryResult, IbcQueryResponse, IbcQueryResponseV2, IbcQueryResult, IbcRequestMsg,
+    PacketMsg, PacketMsgV2, QueryAck, ReceiveIbcQueryGroupResponseMsg, ReceiveIbcQueryResponseMsg,
+    ReceiveIbcResponseMsg, ReceiverExecuteMsg, ResponderQueryMsg, StdAck,
 };
+pub use crate::icq::{
+    CosmosQuery, CosmosResponse, IcqMemo, InterchainQueryPacketAck, InterchainQueryPacketData,
+    RequestQuery, ResponseQuery, ICQ_HOST_PORT, ICQ_VERSION,
+};
+pub use crate::json::Json;
+pub use crate::pagination::{Page, PaginatedQuery, MAX_PAGES};
+pub use crate::polytone::{
+    PolytoneCallback, PolytoneErrorResponse, PolytoneMsg, PolytonePacket, POLYTONE_VERSION,
+};
+pub use crate::projection::{project, Projection, MAX_PROJECTION_PATHS};
+pub use crate::proto::{
+    Acknowledgement, AcknowledgementResponse, ProtoQuery, ProtoQueryKind, ProtoQueryPacket,
+    ProtoQueryResponse, ProtoQueryResult, ProtoQueryResultKind, ProtoResponderRequest,
+    ProtoStargateQuery, ProtoView, ProtoViewParam,
+};
+pub use crate::quorum::{Quorum, QuorumResult};
+pub use crate::stargate::{
+    arithmetic_twap_to_now_query, bank_balance_query, decode_stargate_response, denom_trace_query,
+    encode_stargate_response, gov_proposal_query, spot_price_query, stargate_query,
+    ArithmeticTwapToNowRequest, ArithmeticTwapToNowResponse, DenomTrace, ProtoCoin, ProtoTimestamp,
+    QueryAllBalancesRequest, QueryAllBalancesResponse, QueryBalanceRequest, QueryBalanceResponse,
+    QueryDenomTraceRequest, QueryDenomTraceResponse, QueryProposalRequest,
+    QuerySmartContractStateRequest, QuerySmartContractStateResponse, SpotPriceRequest,
+    SpotPriceResponse, ALL_BALANCES_PATH, ARITHMETIC_TWAP_TO_NOW_PATH, BANK_BALANCE_PATH,
+    DENOM_TRACE_PATH, GOV_PROPOSAL_PATH, RAW_CONTRACT_STATE_PATH, SMART_CONTRACT_STATE_PATH,
+    SPOT_PRICE_PATH,
+};
+pub use crate::version::{AppVersion, Encoding, ProtocolVersion, QueryFamily};
 
 pub const IBC_APP_VERSION: &str = "simple-ica-v1";
+pub const IBC_APP_VERSION_V2: &str = "simple-ica-v2";
+/// Hosts refuse packets with more data than this
+pub const MAX_PACKET_SIZE: usize = 64 * 1024;
 pub const APP_ORDER: IbcOrder = IbcOrder::Unordered;
 // we use this for tests to ensure it is rejected
 pub const BAD_APP_ORDER: IbcOrder = IbcOrder::Ordered;
@@ -0,0 +1,175 @@
+use cosmwasm_std::{to_vec, Binary, QueryRequest, StdError, StdResult, WasmQuery};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::json::Json;
+
+/// Most pages a paginated query fetches, whatever its `max_pages`
+pub const MAX_PAGES: u32 = 50;
+
+/// A smart query on a paginated list, like cw20 `AllAccounts`, followed page by page
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PaginatedQuery {
+    pub contract_addr: String,
+    /// The query for the first page, like `{"all_accounts":{"limit":30}}`
+    pub msg: Binary,
+    /// Field of the query the last key of a page goes in, usually `start_after`
+    pub cursor_field: String,
+    /// Field of the response holding a page, like `accounts`
+    pub items_field: String,
+    /// Field of an item holding its key, like `id`. None if items are their own key.
+    #[serde(default)]
+    pub key_field: Option<String>,
+    /// Most pages to fetch, at most `MAX_PAGES`
+    pub max_pages: u32,
+}
+
+/// One page of a paginated query
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page {
+    /// Every item as JSON
+    pub items: Vec<Binary>,
+    /// Where the next page starts, None if this one was empty
+    pub cursor: Option<Json>,
+}
+
+impl PaginatedQuery {
+    /// Checks the query is a JSON object wrapping the query's fields, and caps `max_pages`
+    pub fn validate(&mut self) -> StdResult<()> {
+        if self.max_pages == 0 {
+            return Err(StdError::generic_err("Must fetch at least one page"));
+        }
+        self.max_pages = self.max_pages.min(MAX_PAGES);
+        self.page_msg(None).map(|_| ())
+    }
+
+    /// The query for the page after `cursor`, or the first one
+    pub fn page<Q>(&self, cursor: Option<Json>) -> StdResult<QueryRequest<Q>> {
+        Ok(QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: self.contract_addr.clone(),
+            msg: self.page_msg(cursor)?,
+        }))
+    }
+
+    fn page_msg(&self, cursor: Option<Json>) -> StdResult<Binary> {
+        let mut msg = Json::from_slice(&self.msg)?;
+        let fields = match &mut msg {
+            Json::Object(variant) if variant.len() == 1 => &mut variant[0].1,
+            _ => {
+                return Err(StdError::generic_err(
+                    "Query must be an object with one field",
+                ))
+            }
+        };
+        if !matches!(fields, Json::Object(_)) {
+            return Err(StdError::generic_err("Query fields must be an object"));
+        }
+        if let Some(cursor) = cursor {
+            fields.insert(&self.cursor_field, cursor)?;
+        }
+        Ok(msg.to_vec()?.into())
+    }
+
+    /// Reads the items of a page response and the key of the last one
+    pub fn read_page(&self, data: &[u8]) -> StdResult<Page> {
+        let items = match Json::from_slice(data)?.get(&self.items_field) {
+            Some(Json::Array(items)) => items.clone(),
+            _ => {
+                return Err(StdError::generic_err(format!(
+                    "Response has no {} list",
+                    self.items_field
+                )))
+            }
+        };
+        let cursor = match (items.last(), &self.key_field) {
+            (None, _) => None,
+            (Some(last), None) => Some(last.clone()),
+            (Some(last), Some(key_field)) => {
+                Some(last.get(key_field).cloned().ok_or_else(|| {
+                    StdError::generic_err(format!("Item has no {} field", key_field))
+                })?)
+            }
+        };
+        let items = items
+            .iter()
+            .map(|item| item.to_vec().map(Binary::from))
+            .collect::<StdResult<_>>()?;
+        Ok(Page { items, cursor })
+    }
+
+    /// All the items, in the shape of a single page
+    pub fn concat(&self, items: &[Binary]) -> StdResult<Binary> {
+        let mut response = b"{".to_vec();
+        response.extend(to_vec(&self.items_field)?);
+        response.extend_from_slice(b":[");
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                response.push(b',');
+            }
+            response.extend_from_slice(item);
+        }
+        response.extend_from_slice(b"]}");
+        Ok(response.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::Empty;
+
+    use super::*;
+
+    fn proposals() -> PaginatedQuery {
+        PaginatedQuery {
+            contract_addr: "dao".to_string(),
+            msg: br#"{"list_proposals":{"limit":2}}"#.into(),
+            cursor_field: "start_after".to_string(),
+            items_field: "proposals".to_string(),
+            key_field: Some("id".to_string()),
+            max_pages: 5,
+        }
+    }
+
+    #[test]
+    fn follows_the_last_key() {
+        let mut query = proposals();
+        query.validate().unwrap();
+        let page = query
+            .read_page(br#"{"proposals":[{"id":1},{"id":2,"title":"b"}]}"#)
+            .unwrap();
+        assert_eq!(
+            query.concat(&page.items).unwrap(),
+            Binary::from(br#"{"proposals":[{"id":1},{"id":2,"title":"b"}]}"#)
+        );
+        assert_eq!(page.cursor, Some(Json::Number(2)));
+        assert_eq!(
+            query.page::<Empty>(page.cursor).unwrap(),
+            QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: "dao".to_string(),
+                msg: br#"{"list_proposals":{"limit":2,"start_after":2}}"#.into(),
+            })
+        );
+
+        let page = query.read_page(br#"{"proposals":[]}"#).unwrap();
+        assert_eq!(page.cursor, None);
+        query.read_page(br#"{"accounts":[]}"#).unwrap_err();
+
+        let mut query = PaginatedQuery {
+            msg: br#"{"list_proposals":5}"#.into(),
+            ..proposals()
+        };
+        query.validate().unwrap_err();
+    }
+
+    #[test]
+    fn caps_the_pages() {
+        let mut query = PaginatedQuery {
+            max_pages: 1000,
+            ..proposals()
+        };
+        query.validate().unwrap();
+        assert_eq!(query.max_pages, MAX_PAGES);
+        query.max_pages = 0;
+        query.validate().unwrap_err();
+    }
+}
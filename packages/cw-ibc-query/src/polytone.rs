@@ -0,0 +1,190 @@
+use cosmwasm_std::{
+    from_slice, to_binary, Binary, ContractResult, QueryRequest, StdError, StdResult, Uint64,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ibc_msg::{IbcQueryResult, PacketMsg, PacketMsgV2};
+
+/// Channel version of Polytone's note and voice
+pub const POLYTONE_VERSION: &str = "polytone-1";
+
+/// The packet a Polytone note sends to a voice. Callbacks are not part of it, the note
+/// remembers them by packet sequence.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PolytonePacket<Q> {
+    /// Who asked the note
+    pub sender: String,
+    pub msg: PolytoneMsg<Q>,
+}
+
+/// Polytone also has an `execute` message, which we don't answer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum PolytoneMsg<Q> {
+    Query { msgs: Vec<QueryRequest<Q>> },
+}
+
+/// The ack of a Polytone voice, sent as is rather than wrapped in `StdAck`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PolytoneCallback {
+    /// All results, or the first query that failed
+    Query(Result<Vec<Binary>, PolytoneErrorResponse>),
+    FatalError(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PolytoneErrorResponse {
+    pub message_index: Uint64,
+    pub error: String,
+}
+
+/// Serializes like `PolytonePacket` without cloning the queries
+#[derive(Serialize)]
+struct PolytonePacketRef<'a, Q> {
+    sender: &'a str,
+    msg: PolytoneMsgRef<'a, Q>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PolytoneMsgRef<'a, Q> {
+    Query { msgs: &'a [QueryRequest<Q>] },
+}
+
+pub(crate) fn encode_packet<Q: Serialize>(packet: &PacketMsgV2<Q>) -> StdResult<Binary> {
+    let msgs = match &packet.msg {
+        PacketMsg::IbcQuery { msgs, .. } => msgs,
+        _ => return Err(StdError::generic_err("Polytone packets only carry queries")),
+    };
+    to_binary(&PolytonePacketRef {
+        sender: &packet.sender,
+        msg: PolytoneMsgRef::Query { msgs },
+    })
+}
+
+/// Polytone packets have no id or callback, they get 0 and an empty callback
+pub(crate) fn decode_packet<Q: serde::de::DeserializeOwned>(
+    data: &[u8],
+) -> StdResult<PacketMsgV2<Q>> {
+    let packet: PolytonePacket<Q> = from_slice(data)?;
+    let PolytoneMsg::Query { msgs } = packet.msg;
+    Ok(PacketMsgV2 {
+        id: 0,
+        sender: packet.sender,
+        msg: PacketMsg::IbcQuery {
+            msgs,
+            callback: String::new(),
+            projections: vec![],
+            chained: false,
+            aggregation: None,
+        },
+    })
+}
+
+/// Polytone queries are all or nothing, the first failure is reported with its index
+pub(crate) fn encode_ack(results: Vec<ContractResult<Binary>>) -> StdResult<Binary> {
+    let result = results
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| {
+            result.into_result().map_err(|error| PolytoneErrorResponse {
+                message_index: Uint64::new(index as u64),
+                error,
+            })
+        })
+        .collect();
+    to_binary(&PolytoneCallback::Query(result))
+}
+
+pub(crate) fn encode_error(err: String) -> Binary {
+    to_binary(&PolytoneCallback::FatalError(err)).unwrap()
+}
+
+pub(crate) fn decode_ack(ack: &[u8]) -> StdResult<IbcQueryResult> {
+    Ok(match from_slice(ack)? {
+        PolytoneCallback::Query(Ok(results)) => {
+            IbcQueryResult::Ok(results.into_iter().map(ContractResult::Ok).collect())
+        }
+        PolytoneCallback::Query(Err(err)) => {
+            IbcQueryResult::Error(format!("Query {} failed: {}", err.message_index, err.error))
+        }
+        PolytoneCallback::FatalError(err) => IbcQueryResult::Error(err),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{BankQuery, Empty};
+
+    use super::*;
+
+    #[test]
+    fn speaks_polytone_json() {
+        let packet = PacketMsgV2 {
+            id: 5,
+            sender: "alice".to_string(),
+            msg: PacketMsg::IbcQuery {
+                msgs: vec![QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
+                    address: "bob".to_string(),
+                })],
+                callback: "callback".to_string(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            },
+        };
+        let data = encode_packet(&packet).unwrap();
+        assert_eq!(
+            data.as_slice(),
+            br#"{"sender":"alice","msg":{"query":{"msgs":[{"bank":{"all_balances":{"address":"bob"}}}]}}}"#
+        );
+        let decoded: PacketMsgV2 = decode_packet(&data).unwrap();
+        assert_eq!(decoded.sender, "alice");
+        assert_eq!(
+            decoded.msg,
+            PacketMsg::IbcQuery {
+                msgs: match packet.msg {
+                    PacketMsg::IbcQuery { msgs, .. } => msgs,
+                    _ => unreachable!(),
+                },
+                callback: String::new(),
+                projections: vec![],
+                chained: false,
+                aggregation: None,
+            }
+        );
+
+        // we only answer queries
+        decode_packet::<Empty>(br#"{"sender":"alice","msg":{"execute":{"msgs":[]}}}"#).unwrap_err();
+    }
+
+    #[test]
+    fn acks_are_all_or_nothing() {
+        let ok = vec![ContractResult::Ok(Binary::from(b"{}"))];
+        let ack = encode_ack(ok.clone()).unwrap();
+        assert_eq!(ack.as_slice(), br#"{"query":{"Ok":["e30="]}}"#);
+        assert_eq!(decode_ack(&ack).unwrap(), IbcQueryResult::Ok(ok));
+
+        let failed = vec![
+            ContractResult::Ok(Binary::from(b"{}")),
+            ContractResult::Err("not found".to_string()),
+        ];
+        let ack = encode_ack(failed).unwrap();
+        assert_eq!(
+            ack.as_slice(),
+            br#"{"query":{"Err":{"message_index":"1","error":"not found"}}}"#
+        );
+        assert_eq!(
+            decode_ack(&ack).unwrap(),
+            IbcQueryResult::Error("Query 1 failed: not found".to_string())
+        );
+
+        assert_eq!(
+            decode_ack(&encode_error("boom".to_string())).unwrap(),
+            IbcQueryResult::Error("boom".to_string())
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use cosmwasm_std::{Binary, ContractResult};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::checks::SimpleIcaError;
+use crate::json::Json;
+
+/// Most paths one projection may pick
+pub const MAX_PROJECTION_PATHS: usize = 16;
+
+/// Picks parts of a query's JSON result on the host, so the ack only carries those
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    /// A JSON pointer like `/balance/amount`, the result is the value there
+    Pointer(String),
+    /// Several JSON pointers, the result is the list of their values
+    Paths(Vec<String>),
+}
+
+impl Projection {
+    fn pointers(&self) -> &[String] {
+        match self {
+            Projection::Pointer(pointer) => std::slice::from_ref(pointer),
+            Projection::Paths(paths) => paths,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SimpleIcaError> {
+        let pointers = self.pointers();
+        if pointers.is_empty() || pointers.len() > MAX_PROJECTION_PATHS {
+            return Err(SimpleIcaError::InvalidProjection(format!(
+                "must pick 1 to {} paths",
+                MAX_PROJECTION_PATHS
+            )));
+        }
+        match pointers
+            .iter()
+            .find(|p| !p.is_empty() && !p.starts_with('/'))
+        {
+            Some(pointer) => Err(SimpleIcaError::InvalidProjection(format!(
+                "{} is not a JSON pointer",
+                pointer
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The picked values of a result, or why they can't be picked
+    pub fn apply(&self, data: &[u8]) -> Result<Binary, String> {
+        let json = Json::from_slice(data).map_err(|_| "Result is not JSON".to_string())?;
+        let mut values = self.pointers().iter().map(|pointer| {
+            json.pointer(pointer)
+                .ok_or_else(|| format!("Path {} not found in result", pointer))
+        });
+        let picked = match self {
+            Projection::Pointer(_) => values.next().unwrap_or(Ok(&Json::Null))?.clone(),
+            Projection::Paths(_) => {
+                Json::Array(values.map(|v| v.cloned()).collect::<Result<_, _>>()?)
+            }
+        };
+        picked
+            .to_vec()
+            .map(Binary::from)
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Applies each query's projection to its result, failed queries stay as they are
+pub fn project(
+    results: Vec<ContractResult<Binary>>,
+    projections: &[Option<Projection>],
+) -> Vec<ContractResult<Binary>> {
+    if projections.is_empty() {
+        return results;
+    }
+    results
+        .into_iter()
+        .zip(projections)
+        .map(|(result, projection)| match (result, projection) {
+            (ContractResult::Ok(data), Some(projection)) => projection.apply(&data).into(),
+            (result, _) => result,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_values() {
+        let data = br#"{"balance":{"amount":"12","denom":"ujuno"},"height":7}"#;
+        let pointer = Projection::Pointer("/balance/amount".to_string());
+        assert_eq!(pointer.apply(data), Ok(Binary::from(br#""12""#)));
+        let paths = Projection::Paths(vec!["/height".to_string(), "/balance/denom".to_string()]);
+        assert_eq!(paths.apply(data), Ok(Binary::from(br#"[7,"ujuno"]"#)));
+
+        let missing = Projection::Pointer("/balance/owner".to_string());
+        let results = project(
+            vec![
+                ContractResult::Ok(data.into()),
+                ContractResult::Ok(data.into()),
+                ContractResult::Err("no such contract".to_string()),
+            ],
+            &[Some(missing.clone()), None, Some(pointer)],
+        );
+        assert_eq!(
+            results,
+            vec![
+                ContractResult::Err("Path /balance/owner not found in result".to_string()),
+                ContractResult::Ok(data.into()),
+                ContractResult::Err("no such contract".to_string()),
+            ]
+        );
+
+        missing.validate().unwrap();
+        Projection::Paths(vec![]).validate().unwrap_err();
+        Projection::Pointer("balance".to_string())
+            .validate()
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,320 @@
+use cosmwasm_std::{from_slice, to_vec, Binary, ContractResult, QueryRequest, StdError, StdResult};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::ibc_msg::{PacketMsg, PacketMsgV2};
+use crate::projection::Projection;
+
+/// `PacketMsgV2` on channels that negotiated `Encoding::Proto`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoQueryPacket {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(string, tag = "2")]
+    pub sender: String,
+    #[prost(string, tag = "3")]
+    pub callback: String,
+    #[prost(message, repeated, tag = "4")]
+    pub queries: Vec<ProtoQuery>,
+    #[prost(bool, tag = "5")]
+    pub chained: bool,
+    /// The JSON `Aggregation` of the packet, empty for none
+    #[prost(bytes, tag = "6")]
+    pub aggregation: Vec<u8>,
+    /// Set on `PacketMsg::View` packets, which carry no queries
+    #[prost(message, optional, tag = "7")]
+    pub view: Option<ProtoView>,
+    /// Set on `PacketMsg::Responder` packets, which carry no queries
+    #[prost(message, optional, tag = "8")]
+    pub responder: Option<ProtoResponderRequest>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoResponderRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(bytes, tag = "2")]
+    pub request: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoView {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub params: Vec<ProtoViewParam>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoViewParam {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+/// Stargate queries are native protobuf already, everything else is the JSON `QueryRequest`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoQuery {
+    #[prost(oneof = "ProtoQueryKind", tags = "1, 2")]
+    pub query: Option<ProtoQueryKind>,
+    /// The JSON `Projection` of the query, empty for none
+    #[prost(bytes, tag = "3")]
+    pub projection: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum ProtoQueryKind {
+    #[prost(bytes, tag = "1")]
+    Json(Vec<u8>),
+    #[prost(message, tag = "2")]
+    Stargate(ProtoStargateQuery),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoStargateQuery {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(bytes, tag = "2")]
+    pub data: Vec<u8>,
+}
+
+/// `IbcQueryResponseV2`, carried in the result of an `Acknowledgement`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoQueryResponse {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub results: Vec<ProtoQueryResult>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoQueryResult {
+    #[prost(oneof = "ProtoQueryResultKind", tags = "1, 2")]
+    pub result: Option<ProtoQueryResultKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum ProtoQueryResultKind {
+    #[prost(bytes, tag = "1")]
+    Ok(Vec<u8>),
+    #[prost(string, tag = "2")]
+    Error(String),
+}
+
+/// ibc.core.channel.v1.Acknowledgement, the protobuf twin of `StdAck`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Acknowledgement {
+    #[prost(oneof = "AcknowledgementResponse", tags = "21, 22")]
+    pub response: Option<AcknowledgementResponse>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum AcknowledgementResponse {
+    #[prost(bytes, tag = "21")]
+    Result(Vec<u8>),
+    #[prost(string, tag = "22")]
+    Error(String),
+}
+
+pub(crate) fn decode<T: prost::Message + Default>(data: &[u8]) -> StdResult<T> {
+    T::decode(data).map_err(|err| StdError::parse_err(std::any::type_name::<T>(), err))
+}
+
+fn missing(field: &str) -> StdError {
+    StdError::parse_err(field, "missing oneof field")
+}
+
+impl ProtoQueryPacket {
+    pub fn from_packet<Q: Serialize>(packet: &PacketMsgV2<Q>) -> StdResult<Self> {
+        let (msgs, callback, projections, chained, aggregation) = match &packet.msg {
+            PacketMsg::IbcQuery {
+                msgs,
+                callback,
+                projections,
+                chained,
+                aggregation,
+            } => (msgs, callback, projections, chained, aggregation),
+            PacketMsg::View {
+                name,
+                params,
+                callback,
+            } => {
+                let params = params
+                    .iter()
+                    .map(|(name, value)| ProtoViewParam {
+                        name: name.clone(),
+                        value: value.clone(),
+                    })
+                    .collect();
+                return Ok(ProtoQueryPacket {
+                    id: packet.id,
+                    sender: packet.sender.clone(),
+                    callback: callback.clone(),
+                    view: Some(ProtoView {
+                        name: name.clone(),
+                        params,
+                    }),
+                    ..Default::default()
+                });
+            }
+            PacketMsg::Responder {
+                name,
+                request,
+                callback,
+            } => {
+                return Ok(ProtoQueryPacket {
+                    id: packet.id,
+                    sender: packet.sender.clone(),
+                    callback: callback.clone(),
+                    responder: Some(ProtoResponderRequest {
+                        name: name.clone(),
+                        request: request.to_vec(),
+                    }),
+                    ..Default::default()
+                });
+            }
+        };
+        let mut queries: Vec<ProtoQuery> = msgs
+            .iter()
+            .map(ProtoQuery::from_query)
+            .collect::<StdResult<_>>()?;
+        for (query, projection) in queries.iter_mut().zip(projections) {
+            if let Some(projection) = projection {
+                query.projection = to_vec(projection)?;
+            }
+        }
+        Ok(ProtoQueryPacket {
+            id: packet.id,
+            sender: packet.sender.clone(),
+            callback: callback.clone(),
+            queries,
+            chained: *chained,
+            aggregation: aggregation
+                .as_ref()
+                .map(to_vec)
+                .transpose()?
+                .unwrap_or_default(),
+            view: None,
+            responder: None,
+        })
+    }
+
+    pub fn into_packet<Q: DeserializeOwned>(self) -> StdResult<PacketMsgV2<Q>> {
+        if let Some(responder) = self.responder {
+            return Ok(PacketMsgV2 {
+                id: self.id,
+                sender: self.sender,
+                msg: PacketMsg::Responder {
+                    name: responder.name,
+                    request: responder.request.into(),
+                    callback: self.callback,
+                },
+            });
+        }
+        if let Some(view) = self.view {
+            return Ok(PacketMsgV2 {
+                id: self.id,
+                sender: self.sender,
+                msg: PacketMsg::View {
+                    name: view.name,
+                    params: view
+                        .params
+                        .into_iter()
+                        .map(|param| (param.name, param.value))
+                        .collect(),
+                    callback: self.callback,
+                },
+            });
+        }
+        let mut projections: Vec<Option<Projection>> = self
+            .queries
+            .iter()
+            .map(|query| {
+                (!query.projection.is_empty())
+                    .then(|| from_slice(&query.projection))
+                    .transpose()
+            })
+            .collect::<StdResult<_>>()?;
+        if projections.iter().all(Option::is_none) {
+            projections.clear();
+        }
+        let aggregation = (!self.aggregation.is_empty())
+            .then(|| from_slice(&self.aggregation))
+            .transpose()?;
+        Ok(PacketMsgV2 {
+            id: self.id,
+            sender: self.sender,
+            msg: PacketMsg::IbcQuery {
+                msgs: self
+                    .queries
+                    .into_iter()
+                    .map(ProtoQuery::into_query)
+                    .collect::<StdResult<_>>()?,
+                callback: self.callback,
+                projections,
+                chained: self.chained,
+                aggregation,
+            },
+        })
+    }
+}
+
+impl ProtoQuery {
+    pub fn from_query<Q: Serialize>(query: &QueryRequest<Q>) -> StdResult<Self> {
+        let query = match query {
+            QueryRequest::Stargate { path, data } => ProtoQueryKind::Stargate(ProtoStargateQuery {
+                path: path.clone(),
+                data: data.to_vec(),
+            }),
+            _ => ProtoQueryKind::Json(to_vec(query)?),
+        };
+        Ok(ProtoQuery {
+            query: Some(query),
+            projection: vec![],
+        })
+    }
+
+    pub fn into_query<Q: DeserializeOwned>(self) -> StdResult<QueryRequest<Q>> {
+        match self.query.ok_or_else(|| missing("ProtoQuery.query"))? {
+            ProtoQueryKind::Json(data) => from_slice(&data),
+            ProtoQueryKind::Stargate(ProtoStargateQuery { path, data }) => {
+                Ok(QueryRequest::Stargate {
+                    path,
+                    data: Binary(data),
+                })
+            }
+        }
+    }
+}
+
+impl From<ContractResult<Binary>> for ProtoQueryResult {
+    fn from(result: ContractResult<Binary>) -> Self {
+        let result = match result {
+            ContractResult::Ok(data) => ProtoQueryResultKind::Ok(data.0),
+            ContractResult::Err(err) => ProtoQueryResultKind::Error(err),
+        };
+        ProtoQueryResult {
+            result: Some(result),
+        }
+    }
+}
+
+impl ProtoQueryResult {
+    pub fn into_result(self) -> StdResult<ContractResult<Binary>> {
+        match self
+            .result
+            .ok_or_else(|| missing("ProtoQueryResult.result"))?
+        {
+            ProtoQueryResultKind::Ok(data) => Ok(ContractResult::Ok(Binary(data))),
+            ProtoQueryResultKind::Error(err) => Ok(ContractResult::Err(err)),
+        }
+    }
+}
+
+impl Acknowledgement {
+    pub fn response(self) -> StdResult<AcknowledgementResponse> {
+        self.response
+            .ok_or_else(|| missing("Acknowledgement.response"))
+    }
+}
@@ -0,0 +1,247 @@
+use cosmwasm_std::{Binary, ContractResult, Decimal, Decimal256, Uint256};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ibc_msg::{ChannelQueryResult, IbcQueryResult};
+
+/// Accept the results of a query group only when enough channels agree on them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Quorum {
+    /// How many channels must return the same results
+    pub threshold: u32,
+    /// Numbers in JSON results may differ by this fraction of the larger one, e.g. 0.01 for 1%.
+    /// Without it results must match byte for byte.
+    pub tolerance: Option<Decimal>,
+}
+
+/// How the channels of a quorum query voted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QuorumResult {
+    /// The results at least `threshold` channels agreed on
+    pub agreed: Option<Vec<ContractResult<Binary>>>,
+    /// Channels that agree with the answer most channels agree with
+    pub majority: Vec<String>,
+    /// Channels that answered something else
+    pub dissenters: Vec<String>,
+}
+
+impl Quorum {
+    /// Picks the answer most channels agree with as the reference, the channels agreeing with
+    /// it form the majority. It wins if the majority reaches the threshold, ties go to the
+    /// lowest channel id, so the order of the answers doesn't matter. Channels that failed or
+    /// timed out don't vote.
+    pub fn tally(&self, results: &[ChannelQueryResult]) -> QuorumResult {
+        let mut answers: Vec<(&str, &[ContractResult<Binary>])> = results
+            .iter()
+            .filter_map(|answer| match &answer.result {
+                IbcQueryResult::Ok(values) => Some((answer.channel_id.as_str(), values.as_slice())),
+                _ => None,
+            })
+            .collect();
+        answers.sort_by_key(|(channel_id, _)| *channel_id);
+
+        // with a tolerance agreement isn't transitive, so every answer is measured against
+        // the same reference
+        let mut reference: Option<(&[ContractResult<Binary>], usize)> = None;
+        for (_, candidate) in &answers {
+            let support = answers
+                .iter()
+                .filter(|(_, values)| self.agree(candidate, values))
+                .count();
+            if reference.is_none_or(|(_, most)| support > most) {
+                reference = Some((candidate, support));
+            }
+        }
+        let (values, support) = match reference {
+            Some(reference) => reference,
+            None => {
+                return QuorumResult {
+                    agreed: None,
+                    majority: vec![],
+                    dissenters: vec![],
+                }
+            }
+        };
+        let (majority, dissenters): (Vec<_>, Vec<_>) = answers
+            .into_iter()
+            .partition(|(_, answer)| self.agree(values, answer));
+        let channels = |answers: Vec<(&str, _)>| {
+            answers
+                .into_iter()
+                .map(|(channel_id, _)| channel_id.to_string())
+                .collect()
+        };
+        QuorumResult {
+            agreed: (support >= self.threshold as usize).then(|| values.to_vec()),
+            majority: channels(majority),
+            dissenters: channels(dissenters),
+        }
+    }
+
+    fn agree(&self, a: &[ContractResult<Binary>], b: &[ContractResult<Binary>]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|pair| match pair {
+                (ContractResult::Ok(a), ContractResult::Ok(b)) => match self.tolerance {
+                    Some(tolerance) => close_enough(a, b, tolerance),
+                    None => a == b,
+                },
+                (ContractResult::Err(a), ContractResult::Err(b)) => a == b,
+                _ => false,
+            })
+    }
+}
+
+/// Compares JSON text byte for byte, except for numbers and decimal strings, which may differ
+/// by `tolerance` relative to the larger one
+fn close_enough(a: &[u8], b: &[u8], tolerance: Decimal) -> bool {
+    // both have 18 decimal places
+    let tolerance = Decimal256::new(Uint256::from(tolerance.atomics()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if starts_number(a, i) && starts_number(b, j) {
+            let (x, y) = (token(a, i), token(b, j));
+            if x != y && !numbers_close(x, y, tolerance) {
+                return false;
+            }
+            i += x.len();
+            j += y.len();
+        } else if a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else {
+            return false;
+        }
+    }
+    i == a.len() && j == b.len()
+}
+
+/// Digits that aren't part of a word, like the 1 in an address
+fn starts_number(data: &[u8], at: usize) -> bool {
+    data[at].is_ascii_digit() && (at == 0 || !is_word(data[at - 1]))
+}
+
+/// The word starting at `at`, only a number if it is all digits and dots
+fn token(data: &[u8], at: usize) -> &[u8] {
+    let len = data[at..].iter().take_while(|&&c| is_word(c)).count();
+    &data[at..at + len]
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'.'
+}
+
+fn numbers_close(x: &[u8], y: &[u8], tolerance: Decimal256) -> bool {
+    let parse = |data| {
+        std::str::from_utf8(data)
+            .ok()
+            .and_then(|s| s.parse::<Decimal256>().ok())
+    };
+    let (x, y) = match (parse(x), parse(y)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return false,
+    };
+    let (low, high) = if x < y { (x, y) } else { (y, x) };
+    high.checked_mul(tolerance)
+        .is_ok_and(|allowed| high - low <= allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(channel_id: &str, data: &str) -> ChannelQueryResult {
+        ChannelQueryResult {
+            channel_id: channel_id.to_string(),
+            result: IbcQueryResult::Ok(vec![ContractResult::Ok(Binary::from(data.as_bytes()))]),
+        }
+    }
+
+    #[test]
+    fn majority_must_reach_threshold() {
+        let quorum = Quorum {
+            threshold: 2,
+            tolerance: None,
+        };
+        let results = vec![
+            answer("channel-1", r#"{"price":"1.5"}"#),
+            answer("channel-2", r#"{"price":"1.6"}"#),
+            answer("channel-3", r#"{"price":"1.5"}"#),
+            ChannelQueryResult {
+                channel_id: "channel-4".to_string(),
+                result: IbcQueryResult::Timeout {},
+            },
+        ];
+        let tally = quorum.tally(&results);
+        assert_eq!(
+            tally.agreed,
+            Some(vec![ContractResult::Ok(Binary::from(
+                br#"{"price":"1.5"}"#
+            ))])
+        );
+        assert_eq!(tally.majority, vec!["channel-1", "channel-3"]);
+        assert_eq!(tally.dissenters, vec!["channel-2"]);
+
+        let tally = quorum.tally(&results[..2]);
+        assert_eq!(tally.agreed, None);
+        assert_eq!(tally.majority, vec!["channel-1"]);
+        assert_eq!(tally.dissenters, vec!["channel-2"]);
+    }
+
+    #[test]
+    fn numbers_agree_within_tolerance() {
+        let tolerance = Decimal::percent(1);
+        let close = |a: &str, b: &str| close_enough(a.as_bytes(), b.as_bytes(), tolerance);
+
+        assert!(close(r#"{"price":"100.5"}"#, r#"{"price":"100"}"#));
+        assert!(close(r#"{"amount":[1000,20]}"#, r#"{"amount":[999,20]}"#));
+        assert!(!close(r#"{"price":"102"}"#, r#"{"price":"100"}"#));
+        // digits inside words must match exactly
+        assert!(!close(r#"{"addr":"osmo1abc"}"#, r#"{"addr":"osmo2abc"}"#));
+        assert!(!close(r#"{"denom":"uosmo"}"#, r#"{"denom":"uatom"}"#));
+
+        let quorum = Quorum {
+            threshold: 2,
+            tolerance: Some(tolerance),
+        };
+        let tally = quorum.tally(&[
+            answer("channel-1", r#"{"price":"1.500"}"#),
+            answer("channel-2", r#"{"price":"1.501"}"#),
+        ]);
+        assert!(tally.agreed.is_some());
+        assert!(tally.dissenters.is_empty());
+    }
+
+    #[test]
+    fn order_does_not_change_the_outcome() {
+        let quorum = Quorum {
+            threshold: 3,
+            tolerance: Some(Decimal::percent(1)),
+        };
+        // 101 is close to both, 100 and 102 aren't close to each other
+        let results = [
+            answer("channel-1", r#"{"price":"100"}"#),
+            answer("channel-2", r#"{"price":"101"}"#),
+            answer("channel-3", r#"{"price":"102"}"#),
+        ];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        for order in orders {
+            let reordered: Vec<_> = order.iter().map(|&i| results[i].clone()).collect();
+            let tally = quorum.tally(&reordered);
+            assert_eq!(
+                tally.agreed,
+                Some(vec![ContractResult::Ok(Binary::from(
+                    br#"{"price":"101"}"#
+                ))])
+            );
+            assert_eq!(tally.majority, vec!["channel-1", "channel-2", "channel-3"]);
+            assert!(tally.dissenters.is_empty());
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use cosmwasm_std::{Binary, QueryRequest, StdError, StdResult};
+
+pub const DENOM_TRACE_PATH: &str = "/ibc.applications.transfer.v1.Query/DenomTrace";
+pub const BANK_BALANCE_PATH: &str = "/cosmos.bank.v1beta1.Query/Balance";
+pub const ALL_BALANCES_PATH: &str = "/cosmos.bank.v1beta1.Query/AllBalances";
+pub const SMART_CONTRACT_STATE_PATH: &str = "/cosmwasm.wasm.v1.Query/SmartContractState";
+pub const RAW_CONTRACT_STATE_PATH: &str = "/cosmwasm.wasm.v1.Query/RawContractState";
+pub const GOV_PROPOSAL_PATH: &str = "/cosmos.gov.v1beta1.Query/Proposal";
+pub const ARITHMETIC_TWAP_TO_NOW_PATH: &str = "/osmosis.twap.v1beta1.Query/ArithmeticTwapToNow";
+pub const SPOT_PRICE_PATH: &str = "/osmosis.poolmanager.v1beta1.Query/SpotPrice";
+
+/// Wraps any protobuf request into a Stargate query for the given gRPC path.
+/// The host will only execute it if `path` is on its allowlist.
+pub fn stargate_query<Q>(
+    path: impl Into<String>,
+    request: &impl prost::Message,
+) -> QueryRequest<Q> {
+    QueryRequest::Stargate {
+        path: path.into(),
+        data: Binary(request.encode_to_vec()),
+    }
+}
+
+/// Stargate results come back protobuf encoded, this decodes one into the expected response type
+pub fn decode_stargate_response<T: prost::Message + Default>(data: &[u8]) -> StdResult<T> {
+    T::decode(data).map_err(|err| StdError::parse_err(std::any::type_name::<T>(), err))
+}
+
+/// Encodes a protobuf response, for hosts answering Stargate queries themselves
+pub fn encode_stargate_response(response: &impl prost::Message) -> Binary {
+    Binary(response.encode_to_vec())
+}
+
+/// Resolves an `ibc/{hash}` denom to its path and base denom.
+/// Returns a protobuf encoded `QueryDenomTraceResponse`.
+pub fn denom_trace_query<Q>(hash: impl Into<String>) -> QueryRequest<Q> {
+    stargate_query(
+        DENOM_TRACE_PATH,
+        &QueryDenomTraceRequest { hash: hash.into() },
+    )
+}
+
+/// Returns a protobuf encoded `QueryBalanceResponse`
+pub fn bank_balance_query<Q>(
+    address: impl Into<String>,
+    denom: impl Into<String>,
+) -> QueryRequest<Q> {
+    stargate_query(
+        BANK_BALANCE_PATH,
+        &QueryBalanceRequest {
+            address: address.into(),
+            denom: denom.into(),
+        },
+    )
+}
+
+/// Returns a protobuf encoded `cosmos.gov.v1beta1.QueryProposalResponse`
+pub fn gov_proposal_query<Q>(proposal_id: u64) -> QueryRequest<Q> {
+    stargate_query(GOV_PROPOSAL_PATH, &QueryProposalRequest { proposal_id })
+}
+
+/// Osmosis arithmetic TWAP from `start_time` (unix seconds) until now.
+/// Returns a protobuf encoded `ArithmeticTwapToNowResponse`.
+pub fn arithmetic_twap_to_now_query<Q>(
+    pool_id: u64,
+    base_asset: impl Into<String>,
+    quote_asset: impl Into<String>,
+    start_time: u64,
+) -> QueryRequest<Q> {
+    stargate_query(
+        ARITHMETIC_TWAP_TO_NOW_PATH,
+        &ArithmeticTwapToNowRequest {
+            pool_id,
+            base_asset: base_asset.into(),
+            quote_asset: quote_asset.into(),
+            start_time: Some(ProtoTimestamp {
+                seconds: start_time as i64,
+                nanos: 0,
+            }),
+        },
+    )
+}
+
+/// Osmosis pool manager spot price.
+/// Returns a protobuf encoded `SpotPriceResponse`.
+pub fn spot_price_query<Q>(
+    pool_id: u64,
+    base_asset_denom: impl Into<String>,
+    quote_asset_denom: impl Into<String>,
+) -> QueryRequest<Q> {
+    stargate_query(
+        SPOT_PRICE_PATH,
+        &SpotPriceRequest {
+            pool_id,
+            base_asset_denom: base_asset_denom.into(),
+            quote_asset_denom: quote_asset_denom.into(),
+        },
+    )
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryDenomTraceRequest {
+    #[prost(string, tag = "1")]
+    pub hash: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryDenomTraceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub denom_trace: Option<DenomTrace>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenomTrace {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub base_denom: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryBalanceRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(string, tag = "2")]
+    pub denom: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryBalanceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub balance: Option<ProtoCoin>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryAllBalancesRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryAllBalancesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub balances: Vec<ProtoCoin>,
+}
+
+/// Also the request of `RAW_CONTRACT_STATE_PATH`, where `query_data` is the key
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QuerySmartContractStateRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(bytes, tag = "2")]
+    pub query_data: Vec<u8>,
+}
+
+/// Also the response of `RAW_CONTRACT_STATE_PATH`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QuerySmartContractStateResponse {
+    #[prost(bytes, tag = "1")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoCoin {
+    #[prost(string, tag = "1")]
+    pub denom: String,
+    #[prost(string, tag = "2")]
+    pub amount: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryProposalRequest {
+    #[prost(uint64, tag = "1")]
+    pub proposal_id: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArithmeticTwapToNowRequest {
+    #[prost(uint64, tag = "1")]
+    pub pool_id: u64,
+    #[prost(string, tag = "2")]
+    pub base_asset: String,
+    #[prost(string, tag = "3")]
+    pub quote_asset: String,
+    #[prost(message, optional, tag = "4")]
+    pub start_time: Option<ProtoTimestamp>,
+}
+
+/// `arithmetic_twap` is an sdk.Dec, i.e. the decimal scaled by 10^18 as an integer string
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArithmeticTwapToNowResponse {
+    #[prost(string, tag = "1")]
+    pub arithmetic_twap: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SpotPriceRequest {
+    #[prost(uint64, tag = "1")]
+    pub pool_id: u64,
+    #[prost(string, tag = "2")]
+    pub base_asset_denom: String,
+    #[prost(string, tag = "3")]
+    pub quote_asset_denom: String,
+}
+
+/// `spot_price` is an sdk.Dec, i.e. the decimal scaled by 10^18 as an integer string
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SpotPriceResponse {
+    #[prost(string, tag = "1")]
+    pub spot_price: String,
+}
+
+/// google.protobuf.Timestamp
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoTimestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::Empty;
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn denom_trace_roundtrip() {
+        let query = denom_trace_query::<Empty>("ABCD");
+        match query {
+            QueryRequest::Stargate { path, data } => {
+                assert_eq!(path, DENOM_TRACE_PATH);
+                let req: QueryDenomTraceRequest = decode_stargate_response(&data).unwrap();
+                assert_eq!(req.hash, "ABCD");
+            }
+            _ => panic!("expected stargate query"),
+        }
+
+        let response = QueryDenomTraceResponse {
+            denom_trace: Some(DenomTrace {
+                path: "transfer/channel-0".to_string(),
+                base_denom: "uatom".to_string(),
+            }),
+        };
+        let decoded: QueryDenomTraceResponse =
+            decode_stargate_response(&response.encode_to_vec()).unwrap();
+        assert_eq!(decoded, response);
+    }
+
+    #[test]
+    fn decode_garbage_fails() {
+        decode_stargate_response::<QueryBalanceResponse>(b"\xff\xff\xff").unwrap_err();
+    }
+}
@@ -0,0 +1,490 @@
+use cosmwasm_std::{to_vec, QueryRequest};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::Aggregation;
+use crate::chain::MAX_CHAIN_STEPS;
+use crate::checks::SimpleIcaError;
+use crate::codec::WireFormat;
+use crate::compression::Compression;
+use crate::icq::ICQ_VERSION;
+use crate::polytone::POLYTONE_VERSION;
+use crate::projection::Projection;
+use crate::{IBC_APP_VERSION, IBC_APP_VERSION_V2};
+
+/// Wire protocols we speak. v2 adds query ids, the sender and structured results.
+/// `Icq` is the async-icq protocol of native Cosmos SDK query hosts, `Polytone` the one
+/// of Polytone's note and voice.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+pub enum ProtocolVersion {
+    #[serde(rename = "simple-ica-v1")]
+    V1,
+    #[serde(rename = "simple-ica-v2")]
+    V2,
+    #[serde(rename = "icq-1")]
+    Icq,
+    #[serde(rename = "polytone-1")]
+    Polytone,
+}
+
+impl ProtocolVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => IBC_APP_VERSION,
+            ProtocolVersion::V2 => IBC_APP_VERSION_V2,
+            ProtocolVersion::Icq => ICQ_VERSION,
+            ProtocolVersion::Polytone => POLYTONE_VERSION,
+        }
+    }
+}
+
+/// The standard (non-stargate, non-custom) query families
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryFamily {
+    Bank,
+    Staking,
+    Wasm,
+    Ibc,
+}
+
+impl QueryFamily {
+    pub const ALL: [QueryFamily; 4] = [
+        QueryFamily::Bank,
+        QueryFamily::Staking,
+        QueryFamily::Wasm,
+        QueryFamily::Ibc,
+    ];
+}
+
+/// Wire encodings for packets and acks, from least to most compact.
+/// A channel uses the most compact one both sides support.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Json,
+    /// The messages in `proto`, acks use the `ibc.core.channel.v1.Acknowledgement` shape
+    Proto,
+}
+
+/// The channel version, a JSON document describing what a side supports.
+/// During the handshake both sides' documents are reduced to their intersection,
+/// which is what the channel may use afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AppVersion {
+    /// Highest protocol version supported
+    pub version: ProtocolVersion,
+    /// Standard query families that can be answered
+    pub query_families: Vec<QueryFamily>,
+    /// Most queries accepted in one packet
+    pub max_batch_size: u32,
+    /// Supported encodings
+    pub encodings: Vec<Encoding>,
+    /// Supported compression codecs, missing from versions that predate compression
+    #[serde(default)]
+    pub compression: Vec<Compression>,
+    /// Whether Stargate queries are answered (still subject to the host's path allowlist)
+    pub stargate: bool,
+    /// Name of the custom query set, if any
+    pub custom_query: Option<String>,
+    /// Whether the host applies projections, missing from versions that predate them
+    #[serde(default)]
+    pub projection: bool,
+    /// Most queries in a chained packet, 0 if chains aren't supported
+    #[serde(default)]
+    pub max_chain_steps: u32,
+    /// Whether the host aggregates results, missing from versions that predate it
+    #[serde(default)]
+    pub aggregation: bool,
+    /// Whether the host runs views, missing from versions that predate them
+    #[serde(default)]
+    pub views: bool,
+    /// Whether the host forwards requests to responder contracts, missing from versions that
+    /// predate them
+    #[serde(default)]
+    pub responders: bool,
+}
+
+impl AppVersion {
+    /// Everything this package knows how to speak, with the given limits
+    pub fn new(max_batch_size: u32, custom_query: Option<&str>) -> Self {
+        AppVersion {
+            version: ProtocolVersion::V2,
+            query_families: QueryFamily::ALL.to_vec(),
+            max_batch_size,
+            encodings: vec![Encoding::Json, Encoding::Proto],
+            compression: vec![Compression::Deflate],
+            stargate: true,
+            custom_query: custom_query.map(String::from),
+            projection: true,
+            max_chain_steps: MAX_CHAIN_STEPS,
+            aggregation: true,
+            views: true,
+            responders: true,
+        }
+    }
+
+    /// What a counterparty from before the version metadata speaks, it sends the plain
+    /// `simple-ica-v1` string and answers any query without limits
+    pub fn legacy() -> Self {
+        AppVersion {
+            version: ProtocolVersion::V1,
+            query_families: QueryFamily::ALL.to_vec(),
+            max_batch_size: u32::MAX,
+            encodings: vec![Encoding::Json],
+            compression: vec![],
+            stargate: true,
+            custom_query: None,
+            projection: false,
+            max_chain_steps: 0,
+            aggregation: false,
+            views: false,
+            responders: false,
+        }
+    }
+
+    /// What a native async-icq host answers: ABCI queries, i.e. Stargate only
+    pub fn icq() -> Self {
+        AppVersion {
+            version: ProtocolVersion::Icq,
+            query_families: vec![],
+            max_batch_size: u32::MAX,
+            encodings: vec![Encoding::Json],
+            compression: vec![],
+            stargate: true,
+            custom_query: None,
+            projection: false,
+            max_chain_steps: 0,
+            aggregation: false,
+            views: false,
+            responders: false,
+        }
+    }
+
+    /// What a Polytone voice answers: any standard or Stargate query
+    pub fn polytone() -> Self {
+        AppVersion {
+            version: ProtocolVersion::Polytone,
+            query_families: QueryFamily::ALL.to_vec(),
+            max_batch_size: u32::MAX,
+            encodings: vec![Encoding::Json],
+            compression: vec![],
+            stargate: true,
+            custom_query: None,
+            projection: false,
+            max_chain_steps: 0,
+            aggregation: false,
+            views: false,
+            responders: false,
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self == &AppVersion::legacy()
+    }
+
+    /// The version string used in the handshake
+    pub fn encode(&self) -> String {
+        if self.is_legacy() {
+            // legacy counterparties compare the version string for equality
+            return IBC_APP_VERSION.to_string();
+        }
+        if matches!(
+            self.version,
+            ProtocolVersion::Icq | ProtocolVersion::Polytone
+        ) {
+            return self.version.as_str().to_string();
+        }
+        // serializing a plain struct of strings and enums cannot fail
+        String::from_utf8(to_vec(self).unwrap()).unwrap()
+    }
+
+    /// The format of packets and acks on a channel with these (negotiated) capabilities
+    pub fn wire_format(&self) -> WireFormat {
+        WireFormat {
+            version: self.version,
+            encoding: self
+                .encodings
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or(Encoding::Json),
+            compression: self.compression.iter().max().copied(),
+        }
+    }
+
+    /// Reduces both sides' capabilities to what they have in common
+    pub fn negotiate(&self, other: &AppVersion) -> Result<AppVersion, SimpleIcaError> {
+        // legacy counterparties cannot negotiate, we have to speak their fixed dialect
+        if self.is_legacy() || other.is_legacy() {
+            return Ok(AppVersion::legacy());
+        }
+        // same for async-icq and Polytone, which have fixed version strings
+        for version in [self, other] {
+            match version.version {
+                ProtocolVersion::Icq => return Ok(AppVersion::icq()),
+                ProtocolVersion::Polytone => return Ok(AppVersion::polytone()),
+                _ => {}
+            }
+        }
+        let encodings: Vec<_> = self
+            .encodings
+            .iter()
+            .filter(|e| other.encodings.contains(e))
+            .copied()
+            .collect();
+        if encodings.is_empty() {
+            return Err(SimpleIcaError::NoCommonEncoding);
+        }
+        let custom_query = match (&self.custom_query, &other.custom_query) {
+            (Some(ours), Some(theirs)) if ours == theirs => Some(ours.clone()),
+            _ => None,
+        };
+        Ok(AppVersion {
+            version: self.version.min(other.version),
+            query_families: self
+                .query_families
+                .iter()
+                .filter(|f| other.query_families.contains(f))
+                .copied()
+                .collect(),
+            max_batch_size: self.max_batch_size.min(other.max_batch_size),
+            encodings,
+            compression: self
+                .compression
+                .iter()
+                .filter(|c| other.compression.contains(c))
+                .copied()
+                .collect(),
+            stargate: self.stargate && other.stargate,
+            custom_query,
+            projection: self.projection && other.projection,
+            max_chain_steps: self.max_chain_steps.min(other.max_chain_steps),
+            aggregation: self.aggregation && other.aggregation,
+            views: self.views && other.views,
+            responders: self.responders && other.responders,
+        })
+    }
+
+    /// Checks a query can be sent on a channel with these (negotiated) capabilities
+    pub fn supports<Q>(&self, query: &QueryRequest<Q>) -> Result<(), SimpleIcaError> {
+        let supported = match query {
+            QueryRequest::Bank(_) => self.query_families.contains(&QueryFamily::Bank),
+            QueryRequest::Staking(_) => self.query_families.contains(&QueryFamily::Staking),
+            QueryRequest::Wasm(_) => self.query_families.contains(&QueryFamily::Wasm),
+            QueryRequest::Ibc(_) => self.query_families.contains(&QueryFamily::Ibc),
+            QueryRequest::Stargate { .. } => self.stargate,
+            QueryRequest::Custom(_) => self.custom_query.is_some(),
+            _ => false,
+        };
+        if supported {
+            Ok(())
+        } else {
+            Err(SimpleIcaError::UnsupportedQuery(
+                query_kind(query).to_string(),
+            ))
+        }
+    }
+
+    /// Checks a whole batch, including its size
+    pub fn supports_all<Q>(&self, queries: &[QueryRequest<Q>]) -> Result<(), SimpleIcaError> {
+        if queries.len() > self.max_batch_size as usize {
+            return Err(SimpleIcaError::BatchTooLarge(self.max_batch_size));
+        }
+        queries.iter().try_for_each(|q| self.supports(q))
+    }
+
+    /// Checks the projections of a batch of `queries` can be sent
+    pub fn supports_projections(
+        &self,
+        projections: &[Option<Projection>],
+        queries: usize,
+    ) -> Result<(), SimpleIcaError> {
+        if projections.is_empty() {
+            return Ok(());
+        }
+        if !self.projection {
+            return Err(SimpleIcaError::ProjectionsUnsupported);
+        }
+        if projections.len() != queries {
+            return Err(SimpleIcaError::ProjectionMismatch {
+                queries,
+                projections: projections.len(),
+            });
+        }
+        projections.iter().flatten().try_for_each(|p| p.validate())
+    }
+
+    /// Checks a chain of `steps` queries can be sent
+    pub fn supports_chain(&self, steps: usize) -> Result<(), SimpleIcaError> {
+        if self.max_chain_steps == 0 {
+            return Err(SimpleIcaError::ChainingUnsupported);
+        }
+        if steps > self.max_chain_steps as usize {
+            return Err(SimpleIcaError::ChainTooLong(self.max_chain_steps));
+        }
+        Ok(())
+    }
+
+    /// Checks a packet's aggregation can be sent
+    pub fn supports_aggregation(
+        &self,
+        aggregation: Option<&Aggregation>,
+    ) -> Result<(), SimpleIcaError> {
+        match aggregation {
+            None => Ok(()),
+            Some(_) if !self.aggregation => Err(SimpleIcaError::AggregationUnsupported),
+            Some(aggregation) => aggregation.validate(),
+        }
+    }
+
+    /// Checks a view can be requested
+    pub fn supports_views(&self) -> Result<(), SimpleIcaError> {
+        if self.views {
+            Ok(())
+        } else {
+            Err(SimpleIcaError::ViewsUnsupported)
+        }
+    }
+
+    /// Checks a responder can be asked
+    pub fn supports_responders(&self) -> Result<(), SimpleIcaError> {
+        if self.responders {
+            Ok(())
+        } else {
+            Err(SimpleIcaError::RespondersUnsupported)
+        }
+    }
+}
+
+fn query_kind<Q>(query: &QueryRequest<Q>) -> &'static str {
+    match query {
+        QueryRequest::Bank(_) => "bank",
+        QueryRequest::Staking(_) => "staking",
+        QueryRequest::Wasm(_) => "wasm",
+        QueryRequest::Ibc(_) => "ibc",
+        QueryRequest::Stargate { .. } => "stargate",
+        QueryRequest::Custom(_) => "custom",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{BankQuery, Binary, Empty, WasmQuery};
+
+    use crate::check_version;
+
+    use super::*;
+
+    #[test]
+    fn negotiate_takes_intersection() {
+        let ours = AppVersion::new(50, Some("osmosis"));
+        let theirs = AppVersion {
+            query_families: vec![QueryFamily::Wasm, QueryFamily::Bank],
+            stargate: false,
+            ..AppVersion::new(10, Some("osmosis"))
+        };
+        let negotiated = ours.negotiate(&theirs).unwrap();
+        assert_eq!(
+            negotiated.query_families,
+            vec![QueryFamily::Bank, QueryFamily::Wasm]
+        );
+        assert_eq!(negotiated.max_batch_size, 10);
+        assert!(!negotiated.stargate);
+        assert_eq!(negotiated.custom_query.as_deref(), Some("osmosis"));
+
+        // different custom query sets don't mix
+        let negotiated = ours.negotiate(&AppVersion::new(10, Some("other"))).unwrap();
+        assert_eq!(negotiated.custom_query, None);
+
+        // the older protocol wins
+        let older_protocol = AppVersion {
+            version: ProtocolVersion::V1,
+            ..AppVersion::new(10, None)
+        };
+        let negotiated = ours.negotiate(&older_protocol).unwrap();
+        assert_eq!(negotiated.version, ProtocolVersion::V1);
+
+        // and legacy counterparties get their plain version string back
+        let negotiated = ours.negotiate(&AppVersion::legacy()).unwrap();
+        assert_eq!(negotiated.encode(), IBC_APP_VERSION);
+
+        // json only counterparties get json
+        let json_only = AppVersion {
+            encodings: vec![Encoding::Json],
+            ..AppVersion::new(10, None)
+        };
+        assert_eq!(ours.wire_format().encoding, Encoding::Proto);
+        let negotiated = ours.negotiate(&json_only).unwrap();
+        assert_eq!(negotiated.wire_format().encoding, Encoding::Json);
+
+        // compression only if both sides support it, versions from before compression don't
+        assert_eq!(
+            negotiated.wire_format().compression,
+            Some(Compression::Deflate)
+        );
+        let before_compression = r#"{"version":"simple-ica-v2","query_families":["bank"],"max_batch_size":10,"encodings":["json"],"stargate":false,"custom_query":null}"#;
+        let theirs = check_version(before_compression).unwrap();
+        assert_eq!(
+            ours.negotiate(&theirs).unwrap().wire_format().compression,
+            None
+        );
+
+        let no_encoding = AppVersion {
+            encodings: vec![],
+            ..AppVersion::new(10, None)
+        };
+        assert_eq!(
+            ours.negotiate(&no_encoding).unwrap_err(),
+            SimpleIcaError::NoCommonEncoding
+        );
+    }
+
+    #[test]
+    fn version_string_roundtrip() {
+        let version = AppVersion::new(50, None);
+        assert_eq!(check_version(&version.encode()).unwrap(), version);
+        assert_eq!(
+            check_version(IBC_APP_VERSION).unwrap(),
+            AppVersion::legacy()
+        );
+        check_version("simple-ica-v2").unwrap_err();
+        check_version(r#"{"version":"simple-ica-v9"}"#).unwrap_err();
+    }
+
+    #[test]
+    fn rejects_unsupported_queries() {
+        let version = AppVersion {
+            query_families: vec![QueryFamily::Bank],
+            stargate: false,
+            ..AppVersion::new(2, None)
+        };
+        let bank = QueryRequest::<Empty>::Bank(BankQuery::AllBalances {
+            address: "foo".to_string(),
+        });
+        let wasm = QueryRequest::<Empty>::Wasm(WasmQuery::Raw {
+            contract_addr: "bar".to_string(),
+            key: Binary::default(),
+        });
+        version.supports(&bank).unwrap();
+        assert_eq!(
+            version.supports_all(&[bank.clone(), wasm]).unwrap_err(),
+            SimpleIcaError::UnsupportedQuery("wasm".to_string())
+        );
+        assert_eq!(
+            version.supports(&QueryRequest::<Empty>::Custom(Empty {})),
+            Err(SimpleIcaError::UnsupportedQuery("custom".to_string()))
+        );
+        assert_eq!(
+            version
+                .supports_all(&[bank.clone(), bank.clone(), bank])
+                .unwrap_err(),
+            SimpleIcaError::BatchTooLarge(2)
+        );
+    }
+}
@@ -8,7 +8,15 @@ const { fundAccount, generateMnemonic, osmosis: oldOsmo, signingCosmWasmClient,
 
 const osmosis = { ...oldOsmo, minFee: "0.025uosmo" };
 
-export const IbcVersion = "simple-ica-v1";
+// The channel version is JSON metadata, the handshake reduces it to what both sides support
+export const IbcVersion = JSON.stringify({
+  version: "simple-ica-v2",
+  query_families: ["bank", "staking", "wasm", "ibc"],
+  max_batch_size: 50,
+  encodings: ["json"],
+  stargate: true,
+  custom_query: null,
+});
 
 export async function setupContracts(
   cosmwasm: CosmWasmSigner,